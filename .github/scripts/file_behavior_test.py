@@ -1,7 +1,24 @@
+import ctypes
+import errno
+import fcntl
+import json
 import os
+import select
+import stat
+import time
 
 TEST_POINT = "/mnt"
 TEST_TEXT = "OpenDAL: access data freely."
+# the behavior test runs ovfs with the default --owner.
+TEST_OWNER = (1000, 1000)
+FALLOC_FL_KEEP_SIZE = 0x01
+FALLOC_FL_PUNCH_HOLE = 0x02
+AT_FDCWD = -100
+RENAME_NOREPLACE = 0x01
+RENAME_EXCHANGE = 0x02
+# _IOR('O', 1, char[8192]) and _IO('O', 2), answered by ovfs itself.
+OVFS_IOC_STATS = 0xA0004F01
+OVFS_IOC_FLUSH_ALL = 0x4F02
 
 def test_file():
     path = os.path.join(TEST_POINT, "test_file.txt")
@@ -23,6 +40,21 @@ def test_file_append():
         assert content == TEST_TEXT * 2
     os.remove(path)
 
+def test_file_append_twice():
+    path = os.path.join(TEST_POINT, "test_file_append_twice.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    fd1 = os.open(path, os.O_WRONLY | os.O_APPEND)
+    fd2 = os.open(path, os.O_WRONLY | os.O_APPEND)
+    os.write(fd1, b"first")
+    os.close(fd1)
+    os.write(fd2, b"second")
+    os.close(fd2)
+    with open(path, "r") as f:
+        content = f.read()
+        assert content == TEST_TEXT + "first" + "second"
+    os.remove(path)
+
 def test_file_seek():
     path = os.path.join(TEST_POINT, "test_file_seek.txt")
     with open(path, "w") as f:
@@ -33,6 +65,27 @@ def test_file_seek():
         assert content == TEST_TEXT[len(TEST_TEXT) // 2:]
     os.remove(path)
 
+def test_file_read_eof():
+    path = os.path.join(TEST_POINT, "test_file_read_eof.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    fd = os.open(path, os.O_RDONLY)
+    assert os.pread(fd, 16, len(TEST_TEXT)) == b""
+    assert os.pread(fd, 16, len(TEST_TEXT) + 1) == b""
+    os.close(fd)
+    os.remove(path)
+
+def test_file_read_range():
+    path = os.path.join(TEST_POINT, "test_file_read_range.txt")
+    data = bytes(i % 251 for i in range(1 << 20))
+    with open(path, "wb") as f:
+        f.write(data)
+    fd = os.open(path, os.O_RDONLY)
+    assert os.pread(fd, 4096, 1 << 19) == data[1 << 19:(1 << 19) + 4096]
+    assert os.pread(fd, 4096, len(data) - 100) == data[-100:]
+    os.close(fd)
+    os.remove(path)
+
 def test_file_truncate():
     path = os.path.join(TEST_POINT, "test_file_truncate.txt")
     with open(path, "w") as f:
@@ -44,8 +97,543 @@ def test_file_truncate():
         assert content == TEST_TEXT[:len(TEST_TEXT) // 2]
     os.remove(path)
 
+def test_file_open_truncate():
+    path = os.path.join(TEST_POINT, "test_file_open_truncate.txt")
+    with open(path, "w") as f:
+        f.write("hello")
+    fd = os.open(path, os.O_WRONLY | os.O_TRUNC)
+    os.write(fd, b"hi")
+    os.close(fd)
+    with open(path, "r") as f:
+        content = f.read()
+        assert content == "hi"
+    os.remove(path)
+
+def test_file_mode():
+    path = os.path.join(TEST_POINT, "test_file_mode.txt")
+    fd = os.open(path, os.O_WRONLY | os.O_CREAT, 0o600)
+    os.write(fd, TEST_TEXT.encode())
+    os.close(fd)
+    assert stat.S_IMODE(os.stat(path).st_mode) == 0o600
+    os.remove(path)
+
+def test_file_chmod():
+    path = os.path.join(TEST_POINT, "test_file_chmod.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    os.chmod(path, 0o640)
+    assert stat.S_IMODE(os.stat(path).st_mode) == 0o640
+    # changing the mode must not touch the content.
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT
+        assert stat.S_IMODE(os.fstat(f.fileno()).st_mode) == 0o640
+    os.remove(path)
+
+def test_file_disk_mode():
+    # the behavior test writes mode_file on the host with mode 0644 and checks that the
+    # chmod below reached the disk.
+    path = os.path.join(TEST_POINT, "mode_file")
+    assert stat.S_IMODE(os.stat(path).st_mode) == 0o644
+    os.chmod(path, 0o600)
+    assert stat.S_IMODE(os.stat(path).st_mode) == 0o600
+
+def test_file_killpriv():
+    path = os.path.join(TEST_POINT, "test_file_killpriv.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    os.chmod(path, 0o4755)
+    assert stat.S_IMODE(os.stat(path).st_mode) == 0o4755
+    # the test runs unprivileged, so writing drops the setuid bit.
+    with open(path, "a") as f:
+        f.write(TEST_TEXT)
+    assert stat.S_IMODE(os.stat(path).st_mode) == 0o755
+    os.remove(path)
+
+def test_file_unlink_open():
+    path = os.path.join(TEST_POINT, "test_file_unlink_open.txt")
+    with open(path, "w+") as f:
+        f.write(TEST_TEXT)
+        f.flush()
+        os.remove(path)
+        assert not os.path.exists(path)
+        f.seek(0)
+        assert f.read() == TEST_TEXT
+        f.write(TEST_TEXT)
+        f.flush()
+        f.seek(0)
+        assert f.read() == TEST_TEXT * 2
+        assert os.fstat(f.fileno()).st_nlink == 0
+        # an unlinked file is held in memory, so it can't grow past --max-file-size.
+        try:
+            os.ftruncate(f.fileno(), 2 << 30)
+            assert False
+        except OSError as e:
+            assert e.errno == errno.EFBIG
+    assert not os.path.exists(path)
+    assert "test_file_unlink_open.txt" not in os.listdir(TEST_POINT)
+
+def test_file_unlink_ftruncate():
+    path = os.path.join(TEST_POINT, "test_file_unlink_ftruncate.txt")
+    with open(path, "w+") as f:
+        f.write(TEST_TEXT)
+        f.flush()
+        os.remove(path)
+        os.ftruncate(f.fileno(), 4)
+        assert os.fstat(f.fileno()).st_size == 4
+        f.seek(0)
+        assert f.read() == TEST_TEXT[:4]
+    assert not os.path.exists(path)
+
+def test_file_ftruncate_too_large():
+    path = os.path.join(TEST_POINT, "test_file_ftruncate_too_large.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+        f.flush()
+        try:
+            os.ftruncate(f.fileno(), 2 << 30)
+            assert False
+        except OSError as e:
+            assert e.errno == errno.EFBIG
+    assert os.path.getsize(path) == len(TEST_TEXT)
+    os.remove(path)
+
+def test_file_owner():
+    path = os.path.join(TEST_POINT, "test_file_owner.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    st = os.stat(path)
+    assert (st.st_uid, st.st_gid) == TEST_OWNER
+    os.remove(path)
+
+def test_file_etag():
+    path = os.path.join(TEST_POINT, "test_file_etag.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    # backends without ETags, like the fs one the test runs on, have no such attribute.
+    try:
+        assert os.getxattr(path, "user.ovfs.etag")
+    except OSError as e:
+        assert e.errno == errno.ENODATA
+    try:
+        os.getxattr(path, "user.ovfs.missing")
+        assert False
+    except OSError as e:
+        assert e.errno == errno.ENODATA
+    os.remove(path)
+
+def test_file_seek_data_hole():
+    path = os.path.join(TEST_POINT, "test_file_seek_data_hole.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    fd = os.open(path, os.O_RDONLY)
+    assert os.lseek(fd, len(TEST_TEXT) // 2, os.SEEK_DATA) == len(TEST_TEXT) // 2
+    assert os.lseek(fd, 0, os.SEEK_HOLE) == len(TEST_TEXT)
+    os.close(fd)
+    os.remove(path)
+
+def test_file_access():
+    path = os.path.join(TEST_POINT, "test_file_access.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    assert os.access(path, os.R_OK)
+    assert os.access(path, os.W_OK)
+    os.remove(path)
+
+def test_file_fallocate():
+    path = os.path.join(TEST_POINT, "test_file_fallocate.txt")
+    with open(path, "wb") as f:
+        f.write(TEST_TEXT.encode())
+    fd = os.open(path, os.O_RDWR)
+    os.posix_fallocate(fd, 0, 100)
+    try:
+        os.posix_fallocate(fd, 0, 2 << 30)
+        assert False
+    except OSError as e:
+        assert e.errno == errno.EFBIG
+    os.close(fd)
+    assert os.path.getsize(path) == 100
+    with open(path, "rb") as f:
+        content = f.read()
+        assert content == TEST_TEXT.encode() + bytes(100 - len(TEST_TEXT))
+    os.remove(path)
+
+def test_file_punch_hole():
+    libc = ctypes.CDLL(None, use_errno=True)
+    path = os.path.join(TEST_POINT, "test_file_punch_hole.txt")
+    with open(path, "wb") as f:
+        f.write(TEST_TEXT.encode())
+    fd = os.open(path, os.O_RDWR)
+    ret = libc.fallocate(fd, FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE, ctypes.c_long(4), ctypes.c_long(4))
+    assert ret == 0
+    os.close(fd)
+    with open(path, "rb") as f:
+        content = f.read()
+        assert content == TEST_TEXT.encode()[:4] + bytes(4) + TEST_TEXT.encode()[8:]
+    os.remove(path)
+
+def test_file_blocks():
+    path = os.path.join(TEST_POINT, "test_file_blocks.txt")
+    with open(path, "wb") as f:
+        f.write(bytes(5000))
+    st = os.stat(path)
+    assert st.st_blocks == 10
+    assert st.st_blksize == 4096
+    os.remove(path)
+
+def test_file_small_writes():
+    path = os.path.join(TEST_POINT, "test_file_small_writes.txt")
+    chunk = bytes(range(256)) * 16
+    fd = os.open(path, os.O_RDWR | os.O_CREAT, 0o644)
+    for _ in range(1000):
+        os.write(fd, chunk)
+    assert os.pread(fd, len(chunk), 999 * len(chunk)) == chunk
+    os.close(fd)
+    assert os.path.getsize(path) == 1000 * len(chunk)
+    with open(path, "rb") as f:
+        content = f.read()
+        assert content == chunk * 1000
+    os.remove(path)
+
+def test_file_large_write():
+    path = os.path.join(TEST_POINT, "test_file_large_write.bin")
+    # four times the 1MiB max_write, so the guest splits it into back to back requests.
+    data = bytes(i % 251 for i in range(4 << 20))
+    fd = os.open(path, os.O_WRONLY | os.O_CREAT | os.O_TRUNC, 0o644)
+    written = 0
+    while written < len(data):
+        written += os.write(fd, data[written:])
+    os.close(fd)
+    assert os.path.getsize(path) == len(data)
+    with open(path, "rb") as f:
+        assert f.read() == data
+    os.remove(path)
+
+def test_file_direct_io():
+    path = os.path.join(TEST_POINT, "test_file_direct_io.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    # the workflow checks that the open below was answered with FOPEN_DIRECT_IO.
+    fd = os.open(path, os.O_RDWR | os.O_DIRECT)
+    assert os.pwrite(fd, TEST_TEXT.encode(), len(TEST_TEXT)) == len(TEST_TEXT)
+    assert os.pread(fd, len(TEST_TEXT) * 2, 0) == (TEST_TEXT * 2).encode()
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT * 2
+    os.close(fd)
+    os.remove(path)
+
+def test_file_two_handles():
+    path = os.path.join(TEST_POINT, "test_file_two_handles.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    fd1 = os.open(path, os.O_WRONLY | os.O_TRUNC)
+    fd2 = os.open(path, os.O_WRONLY)
+    os.write(fd1, TEST_TEXT.encode())
+    # the idle handle must not replace what the other one wrote.
+    os.close(fd2)
+    os.write(fd1, TEST_TEXT.encode())
+    os.close(fd1)
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT * 2
+    os.remove(path)
+
+def test_file_create_mtime():
+    path = os.path.join(TEST_POINT, "test_file_create_mtime.txt")
+    fd = os.open(path, os.O_WRONLY | os.O_CREAT, 0o644)
+    # answered from the attributes cached from the create reply.
+    assert os.fstat(fd).st_mtime > 0
+    os.close(fd)
+    os.remove(path)
+
+def test_file_mtime():
+    path = os.path.join(TEST_POINT, "test_file_mtime.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    # the backend's modification time, not the epoch.
+    st = os.stat(path)
+    assert abs(st.st_mtime - time.time()) < 60
+    assert st.st_ctime == st.st_mtime
+    os.remove(path)
+
+def test_file_reopen():
+    path = os.path.join(TEST_POINT, "test_file_reopen.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    for _ in range(16):
+        fd = os.open(path, os.O_RDONLY)
+        assert os.read(fd, len(TEST_TEXT)) == TEST_TEXT.encode()
+        os.close(fd)
+    os.remove(path)
+
+def test_file_no_space():
+    # the behavior test mounts a 1MiB tmpfs at full_dir on the host.
+    path = os.path.join(TEST_POINT, "full_dir", "test_file_no_space.bin")
+    try:
+        with open(path, "wb") as f:
+            f.write(b"\0" * (2 << 20))
+            f.flush()
+            os.fsync(f.fileno())
+        assert False
+    except OSError as e:
+        assert e.errno in (errno.ENOSPC, errno.EDQUOT)
+    if os.path.exists(path):
+        os.remove(path)
+
+def test_file_poll():
+    path = os.path.join(TEST_POINT, "test_file_poll.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    fd = os.open(path, os.O_RDWR)
+    poller = select.poll()
+    poller.register(fd, select.POLLIN | select.POLLOUT)
+    events = poller.poll(1000)
+    assert events == [(fd, select.POLLIN | select.POLLOUT)]
+    os.close(fd)
+    os.remove(path)
+
+def test_file_dax_fallback():
+    # the test VM has no DAX window, so reads must still be copied even with DAX enabled.
+    with open("/proc/mounts") as f:
+        options = [line.split()[3] for line in f if line.split()[1] == TEST_POINT]
+    assert options and "dax" not in options[0]
+    path = os.path.join(TEST_POINT, "test_file_dax_fallback.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    for _ in range(3):
+        with open(path, "r") as f:
+            assert f.read() == TEST_TEXT
+    os.remove(path)
+
+def test_file_fsync():
+    path = os.path.join(TEST_POINT, "test_file_fsync.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+        f.flush()
+        os.fsync(f.fileno())
+        with open(path, "r") as r:
+            content = r.read()
+            assert content == TEST_TEXT
+    os.remove(path)
+
+def test_file_flush():
+    path = os.path.join(TEST_POINT, "test_file_flush.txt")
+    fd = os.open(path, os.O_WRONLY | os.O_CREAT | os.O_TRUNC, 0o644)
+    os.write(fd, TEST_TEXT.encode())
+    # closing a duplicate sends a flush while the handle stays open. only backends that
+    # persist on write, such as the fs one this test runs on, show the flushed data before
+    # the handle is closed.
+    os.close(os.dup(fd))
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT
+    os.write(fd, TEST_TEXT.encode())
+    os.close(fd)
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT * 2
+    os.remove(path)
+
+def test_file_sparse_write():
+    path = os.path.join(TEST_POINT, "test_file_sparse_write.txt")
+    with open(path, "wb") as f:
+        f.write(TEST_TEXT.encode())
+        f.seek(100)
+        f.write(TEST_TEXT.encode())
+        # writes continuing after the seek stream again.
+        f.write(TEST_TEXT.encode())
+    with open(path, "rb") as f:
+        content = f.read()
+        assert content == TEST_TEXT.encode() + bytes(100 - len(TEST_TEXT)) + TEST_TEXT.encode() * 2
+    os.remove(path)
+
+def test_file_resize():
+    path = os.path.join(TEST_POINT, "test_file_resize.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT[:10])
+    os.truncate(path, 4)
+    assert os.path.getsize(path) == 4
+    with open(path, "r") as f:
+        content = f.read()
+        assert content == TEST_TEXT[:4]
+    os.remove(path)
+
+def test_file_resize_too_large():
+    path = os.path.join(TEST_POINT, "test_file_resize_too_large.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    # growing a file rebuilds it in memory, which stops at --max-file-size.
+    try:
+        os.truncate(path, 2 << 30)
+        assert False
+    except OSError as e:
+        assert e.errno == errno.EFBIG
+    assert os.path.getsize(path) == len(TEST_TEXT)
+    os.remove(path)
+
+def test_file_rename():
+    path = os.path.join(TEST_POINT, "test_file_rename.txt")
+    new_path = os.path.join(TEST_POINT, "test_file_rename_new.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    with open(new_path, "w") as f:
+        f.write(TEST_TEXT[:len(TEST_TEXT) // 2])
+    os.rename(path, new_path)
+    assert not os.path.exists(path)
+    with open(new_path, "r") as f:
+        content = f.read()
+        assert content == TEST_TEXT
+    os.remove(new_path)
+
+def test_file_rename_dir():
+    old_dir = os.path.join(TEST_POINT, "test_file_rename_dir")
+    new_dir = os.path.join(TEST_POINT, "test_file_rename_dir_new")
+    os.mkdir(old_dir)
+    with open(os.path.join(old_dir, "file.txt"), "w") as f:
+        f.write(TEST_TEXT)
+    # files open below a renamed directory follow it.
+    with open(os.path.join(old_dir, "file.txt"), "r") as f:
+        os.rename(old_dir, new_dir)
+        assert f.read() == TEST_TEXT
+        assert os.fstat(f.fileno()).st_size == len(TEST_TEXT)
+    path = os.path.join(new_dir, "file.txt")
+    assert os.stat(path).st_size == len(TEST_TEXT)
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT
+    os.remove(path)
+    os.rmdir(new_dir)
+
+def test_file_atomic_save():
+    path = os.path.join(TEST_POINT, "test_file_atomic_save.txt")
+    tmp_path = os.path.join(TEST_POINT, "test_file_atomic_save.txt.tmp")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT[:len(TEST_TEXT) // 2])
+    # the temporary file is only closed after it took the target's place.
+    with open(tmp_path, "w") as f:
+        f.write(TEST_TEXT * 64)
+        f.flush()
+        os.fsync(f.fileno())
+        os.rename(tmp_path, path)
+    assert not os.path.exists(tmp_path)
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT * 64
+    os.remove(path)
+
+def test_file_rename_noreplace():
+    libc = ctypes.CDLL(None, use_errno=True)
+    path = os.path.join(TEST_POINT, "test_file_rename_noreplace.txt")
+    new_path = os.path.join(TEST_POINT, "test_file_rename_noreplace_new.txt")
+    for p, text in [(path, TEST_TEXT), (new_path, TEST_TEXT[:4])]:
+        with open(p, "w") as f:
+            f.write(text)
+    ret = libc.renameat2(AT_FDCWD, path.encode(), AT_FDCWD, new_path.encode(), RENAME_NOREPLACE)
+    assert ret == -1 and ctypes.get_errno() == errno.EEXIST
+    with open(new_path, "r") as f:
+        assert f.read() == TEST_TEXT[:4]
+    os.remove(path)
+    os.remove(new_path)
+
+def test_file_rename_exchange():
+    libc = ctypes.CDLL(None, use_errno=True)
+    path = os.path.join(TEST_POINT, "test_file_rename_exchange.txt")
+    new_path = os.path.join(TEST_POINT, "test_file_rename_exchange_new.txt")
+    for p, text in [(path, TEST_TEXT), (new_path, TEST_TEXT[:4])]:
+        with open(p, "w") as f:
+            f.write(text)
+    ret = libc.renameat2(AT_FDCWD, path.encode(), AT_FDCWD, new_path.encode(), RENAME_EXCHANGE)
+    assert ret == 0
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT[:4]
+    with open(new_path, "r") as f:
+        assert f.read() == TEST_TEXT
+    os.remove(path)
+    os.remove(new_path)
+
+def test_file_link():
+    path = os.path.join(TEST_POINT, "test_file_link.txt")
+    link_path = os.path.join(TEST_POINT, "test_file_link_new.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    os.link(path, link_path)
+    for p in [path, link_path]:
+        with open(p, "r") as f:
+            content = f.read()
+            assert content == TEST_TEXT
+    os.remove(path)
+    os.remove(link_path)
+
+def test_file_ioctl():
+    path = os.path.join(TEST_POINT, "test_file_ioctl.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+        f.flush()
+        assert fcntl.ioctl(f.fileno(), OVFS_IOC_FLUSH_ALL) >= 0
+        buf = bytearray(8192)
+        fcntl.ioctl(f.fileno(), OVFS_IOC_STATS, buf)
+        stats = json.loads(buf[:buf.index(0)])
+        assert stats["requests"] > 0 and stats["opcodes"]
+        try:
+            fcntl.ioctl(f.fileno(), 0x4F7F)
+            assert False
+        except OSError as e:
+            assert e.errno == errno.EINVAL
+    with open(path, "r") as f:
+        assert f.read() == TEST_TEXT
+    os.remove(path)
+
+def test_file_copy_range():
+    path = os.path.join(TEST_POINT, "test_file_copy_range.txt")
+    copy_path = os.path.join(TEST_POINT, "test_file_copy_range_new.txt")
+    with open(path, "w") as f:
+        f.write(TEST_TEXT)
+    with open(path, "rb") as src, open(copy_path, "wb") as dst:
+        copied = os.copy_file_range(src.fileno(), dst.fileno(), len(TEST_TEXT))
+        assert copied == len(TEST_TEXT)
+    with open(copy_path, "r") as f:
+        content = f.read()
+        assert content == TEST_TEXT
+    os.remove(path)
+    os.remove(copy_path)
+
 if __name__ == "__main__":
     test_file()
     test_file_append()
+    test_file_append_twice()
     test_file_seek()
+    test_file_read_eof()
+    test_file_read_range()
     test_file_truncate()
+    test_file_open_truncate()
+    test_file_mode()
+    test_file_chmod()
+    test_file_disk_mode()
+    test_file_owner()
+    test_file_killpriv()
+    test_file_etag()
+    test_file_seek_data_hole()
+    test_file_access()
+    test_file_fallocate()
+    test_file_punch_hole()
+    test_file_blocks()
+    test_file_small_writes()
+    test_file_large_write()
+    test_file_two_handles()
+    test_file_direct_io()
+    test_file_unlink_open()
+    test_file_unlink_ftruncate()
+    test_file_ftruncate_too_large()
+    test_file_no_space()
+    test_file_reopen()
+    test_file_create_mtime()
+    test_file_mtime()
+    test_file_poll()
+    test_file_dax_fallback()
+    test_file_fsync()
+    test_file_flush()
+    test_file_sparse_write()
+    test_file_resize()
+    test_file_resize_too_large()
+    test_file_rename()
+    test_file_rename_dir()
+    test_file_atomic_save()
+    test_file_rename_noreplace()
+    test_file_rename_exchange()
+    test_file_link()
+    test_file_copy_range()
+    test_file_ioctl()
@@ -1,14 +1,19 @@
+import errno
 import os
+import stat
 from pathlib import Path
 
 TEST_POINT = "/mnt"
 TEST_PRESET_PATHS = [
     ("./behavior_test_judge.py", True),
     ("./file_behavior_test.py", True),
+    ("./full_dir", False),
     ("./image.img", True),
     ("./install_and_run_vm.sh", True),
     ("./meta-data", True),
+    ("./mode_file", True),
     ("./path_behavior_test.py", True),
+    ("./plus_dir", False),
     ("./seed.iso", True),
     ("./ubuntu-20.04.6-live-server-amd64.iso", True),
     ("./user-data", True),
@@ -52,6 +57,219 @@ def test_nested_path():
     paths = list_paths()
     assert paths.sort() == (TEST_NESTED_PATHS + TEST_PRESET_PATHS).sort()
 
+def test_large_dir():
+    path = Path(TEST_POINT) / "large_dir"
+    os.makedirs(path, exist_ok=False)
+    names = ["file{}".format(i) for i in range(1000)]
+    for name in names:
+        with open(path / name, "w") as f:
+            f.write("This is a file.")
+    assert sorted(os.listdir(path)) == sorted(names)
+
+def test_interleaved_listing():
+    path = Path(TEST_POINT) / "interleaved_dir"
+    os.makedirs(path, exist_ok=False)
+    names = ["file{}".format(i) for i in range(2000)]
+    for name in names:
+        with open(path / name, "w") as f:
+            f.write("This is a file.")
+    # each open directory streams its own listing a page at a time.
+    first = os.scandir(path)
+    second = os.scandir(path)
+    listed_first = []
+    listed_second = []
+    for entry in first:
+        listed_first.append(entry.name)
+        entry = next(second, None)
+        if entry is not None:
+            listed_second.append(entry.name)
+    listed_second.extend(entry.name for entry in second)
+    first.close()
+    second.close()
+    assert sorted(listed_first) == sorted(names)
+    assert sorted(listed_second) == sorted(names)
+    for name in names:
+        os.remove(path / name)
+    os.rmdir(path)
+
+def test_many_inodes():
+    # the behavior test runs ovfs with a small --max-inodes.
+    path = Path(TEST_POINT) / "many_inodes_dir"
+    os.makedirs(path, exist_ok=False)
+    with open(path / "opened", "w") as f:
+        f.write("This is a file.")
+    fd = os.open(path / "opened", os.O_RDONLY)
+    names = ["file{}".format(i) for i in range(512)]
+    for name in names:
+        with open(path / name, "w") as f:
+            f.write(name)
+    assert sorted(os.listdir(path)) == sorted(names + ["opened"])
+    for name in names:
+        with open(path / name, "r") as f:
+            assert f.read() == name
+    assert os.pread(fd, 64, 0) == b"This is a file."
+    os.close(fd)
+    for name in names + ["opened"]:
+        os.remove(path / name)
+    os.rmdir(path)
+
+def test_statfs():
+    stat = os.statvfs(TEST_POINT)
+    assert stat.f_bsize == 4096
+    assert stat.f_namemax == 255
+
+def test_create_after_missing_lookup():
+    path = Path(TEST_POINT) / "missing_file"
+    assert not path.exists()
+    with open(path, "w") as f:
+        f.write("This is a file.")
+    assert path.exists()
+    os.remove(path)
+
+def test_dir_mode():
+    path = Path(TEST_POINT) / "mode_dir"
+    os.mkdir(path, 0o700)
+    assert stat.S_IMODE(os.stat(path).st_mode) == 0o700
+    os.rmdir(path)
+
+def test_list_after_create():
+    path = Path(TEST_POINT) / "list_dir"
+    os.makedirs(path, exist_ok=False)
+    assert os.listdir(path) == []
+    with open(path / "file", "w") as f:
+        f.write("This is a file.")
+    assert os.listdir(path) == ["file"]
+    os.remove(path / "file")
+    assert os.listdir(path) == []
+    os.rmdir(path)
+
+def test_empty_dir():
+    path = Path(TEST_POINT) / "empty_dir"
+    os.mkdir(path)
+    # only a directory marker exists on object stores and the memory service.
+    assert "empty_dir" in os.listdir(TEST_POINT)
+    assert stat.S_ISDIR(os.stat(path).st_mode)
+    assert os.listdir(path) == []
+    os.rmdir(path)
+    assert "empty_dir" not in os.listdir(TEST_POINT)
+
+def test_mkdir_existing():
+    path = Path(TEST_POINT) / "existing_dir"
+    os.mkdir(path)
+    try:
+        os.mkdir(path)
+        assert False
+    except FileExistsError:
+        pass
+    os.makedirs(path, exist_ok=True)
+    with open(path / "file", "w") as f:
+        f.write("This is a file.")
+    try:
+        os.mkdir(path / "file")
+        assert False
+    except (FileExistsError, NotADirectoryError):
+        pass
+    os.remove(path / "file")
+    os.rmdir(path)
+
+def test_name_max():
+    path = Path(TEST_POINT) / ("a" * 255)
+    with open(path, "w") as f:
+        f.write("This is a file.")
+    assert os.stat(path).st_size == len("This is a file.")
+    os.remove(path)
+    assert os.statvfs(TEST_POINT).f_namemax == 255
+    try:
+        open(Path(TEST_POINT) / ("a" * 256), "w")
+        assert False
+    except OSError as e:
+        assert e.errno == errno.ENAMETOOLONG
+
+def test_dir_nlink():
+    # the behavior test runs ovfs with --count-subdirs.
+    path = Path(TEST_POINT) / "nlink_dir"
+    os.mkdir(path)
+    os.mkdir(path / "sub1")
+    os.mkdir(path / "sub2")
+    with open(path / "file", "w") as f:
+        f.write("This is a file.")
+    assert os.stat(path).st_nlink == 4
+    os.remove(path / "file")
+    os.rmdir(path / "sub1")
+    os.rmdir(path / "sub2")
+    os.rmdir(path)
+
+def test_list_deep_dir():
+    path = Path(TEST_POINT) / "deep_dir"
+    os.makedirs(path / "a" / "b")
+    with open(path / "a" / "b" / "c", "w") as f:
+        f.write("This is a file.")
+    entries = list(os.scandir(path))
+    assert [entry.name for entry in entries] == ["a"]
+    assert entries[0].is_dir()
+    os.remove(path / "a" / "b" / "c")
+    os.rmdir(path / "a" / "b")
+    os.rmdir(path / "a")
+    os.rmdir(path)
+
+def test_fsync_dir():
+    path = Path(TEST_POINT) / "fsync_dir"
+    os.makedirs(path, exist_ok=False)
+    files = [open(path / name, "w") for name in ["file1", "file2"]]
+    for f in files:
+        f.write("This is a file.")
+        f.flush()
+    fd = os.open(path, os.O_RDONLY)
+    os.fsync(fd)
+    os.close(fd)
+    assert sorted(os.listdir(path)) == ["file1", "file2"]
+    for f in files:
+        f.close()
+    for name in ["file1", "file2"]:
+        os.remove(path / name)
+    os.rmdir(path)
+
+def test_readdirplus():
+    # the behavior test writes these files on the host, so the guest has never looked them up.
+    # the workflow checks that the stats below were answered without a lookup, and that
+    # none of them asked the backend once the listing prefetched the entries.
+    path = Path(TEST_POINT) / "plus_dir"
+    entries = sorted(os.scandir(path), key=lambda entry: entry.name)
+    assert [entry.name for entry in entries] == ["plus_file{}".format(i) for i in range(10)]
+    for i, entry in enumerate(entries):
+        st = os.stat(entry.path)
+        assert stat.S_ISREG(st.st_mode)
+        assert st.st_size == i
+
+def test_case_insensitive_lookup():
+    # the behavior test runs ovfs with --case-insensitive.
+    path = Path(TEST_POINT) / "case_dir"
+    os.makedirs(path, exist_ok=False)
+    with open(path / "file.txt", "w") as f:
+        f.write("This is a file.")
+    with open(path / "FILE.TXT", "r") as f:
+        assert f.read() == "This is a file."
+    assert os.stat(path / "FILE.TXT").st_ino == os.stat(path / "file.txt").st_ino
+    assert os.listdir(path) == ["file.txt"]
+    os.remove(path / "FILE.TXT")
+    assert os.listdir(path) == []
+    os.rmdir(path)
+
 if __name__ == "__main__":
     test_path()
     test_nested_path()
+    test_large_dir()
+    test_interleaved_listing()
+    test_many_inodes()
+    test_statfs()
+    test_create_after_missing_lookup()
+    test_dir_mode()
+    test_list_after_create()
+    test_empty_dir()
+    test_list_deep_dir()
+    test_mkdir_existing()
+    test_fsync_dir()
+    test_readdirplus()
+    test_case_insensitive_lookup()
+    test_name_max()
+    test_dir_nlink()
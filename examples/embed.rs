@@ -0,0 +1,49 @@
+//! Builds an ovfs daemon serving the memory service and shuts it down again, the way a
+//! VMM embedding ovfs would before starting it.
+
+use std::path::Path;
+use std::time::Duration;
+
+use opendal::services::Memory;
+use opendal::Operator;
+use ovfs::error::Result;
+use ovfs::FilesystemConfig;
+use ovfs::OvfsBuilder;
+
+const SOCKET_PATH: &str = "/tmp/ovfs-embed.sock";
+
+fn main() -> Result<()> {
+    let operator = Operator::new(Memory::default())?.finish();
+    let config = FilesystemConfig {
+        worker_threads: 1,
+        read_ahead_size: 0,
+        negative_lookup_ttl: Duration::from_secs(1),
+        dir_cache_ttl: Duration::from_secs(1),
+        entry_timeout: Duration::from_secs(1),
+        attr_timeout: Duration::from_secs(1),
+        max_buffer_size: 1 << 20,
+        write_buffer_size: 4 << 20,
+        read_only: false,
+        dax: false,
+        writeback: false,
+        verify_writes: false,
+        compression: None,
+        content_types: None,
+        count_subdirs: false,
+        readdirplus: false,
+        case_insensitive: false,
+        uid: 1000,
+        gid: 1000,
+        passthrough_owner: false,
+        max_inodes: 0,
+        max_file_size: 1 << 30,
+        prefetch_stats: 0,
+    };
+    let daemon = OvfsBuilder::new(operator, SOCKET_PATH, config).build()?;
+    assert!(Path::new(SOCKET_PATH).exists());
+    // a VMM would call start here, which waits for it to connect.
+    daemon.shutdown()?;
+    assert!(!Path::new(SOCKET_PATH).exists());
+    println!("daemon built and shut down");
+    Ok(())
+}
@@ -0,0 +1,10 @@
+//! Decodes arbitrary bytes as a FUSE request, which must fail without panicking when they are
+//! malformed.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = ovfs::decode_request(data);
+});
@@ -0,0 +1,390 @@
+use std::cmp::min;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use opendal::Buffer;
+use opendal::Metadata;
+
+struct ReadCacheEntry {
+    path: String,
+    offset: u64,
+    data: Buffer,
+    eof: bool,
+}
+
+impl ReadCacheEntry {
+    fn covers(&self, path: &str, offset: u64, size: u64) -> bool {
+        let end = self.offset + self.data.len() as u64;
+        self.path == path && self.offset <= offset && (offset + size <= end || self.eof)
+    }
+}
+
+pub struct ReadCache {
+    capacity: usize,
+    entries: VecDeque<ReadCacheEntry>,
+}
+
+impl ReadCache {
+    pub fn new(capacity: usize) -> ReadCache {
+        ReadCache {
+            capacity,
+            entries: VecDeque::new(),
+        }
+    }
+
+    pub fn get(&mut self, path: &str, offset: u64, size: u64) -> Option<Buffer> {
+        let pos = self
+            .entries
+            .iter()
+            .position(|entry| entry.covers(path, offset, size))?;
+        let entry = self.entries.remove(pos)?;
+        let start = min((offset - entry.offset) as usize, entry.data.len());
+        let end = min(start + size as usize, entry.data.len());
+        let data = entry.data.slice(start..end);
+        self.entries.push_front(entry);
+        Some(data)
+    }
+
+    pub fn insert(&mut self, path: &str, offset: u64, data: Buffer, eof: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.entries.push_front(ReadCacheEntry {
+            path: path.to_string(),
+            offset,
+            data,
+            eof,
+        });
+        self.entries.truncate(self.capacity);
+    }
+
+    pub fn invalidate(&mut self, path: &str) {
+        self.entries.retain(|entry| entry.path != path);
+    }
+
+    pub fn invalidate_matching(&mut self, matches: impl Fn(&str) -> bool) {
+        self.entries.retain(|entry| !matches(&entry.path));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+pub struct NegativeLookupCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: HashMap<(u64, String), Instant>,
+}
+
+impl NegativeLookupCache {
+    pub fn new(ttl: Duration, capacity: usize) -> NegativeLookupCache {
+        NegativeLookupCache {
+            ttl,
+            capacity,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&mut self, parent: u64, name: &str) -> bool {
+        let key = (parent, name.to_string());
+        match self.entries.get(&key) {
+            Some(expires) if *expires > Instant::now() => true,
+            Some(_) => {
+                self.entries.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, parent: u64, name: &str) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        if self.entries.len() >= self.capacity {
+            self.entries.retain(|_, expires| *expires > now);
+            if self.entries.len() >= self.capacity {
+                return;
+            }
+        }
+        self.entries
+            .insert((parent, name.to_string()), now + self.ttl);
+    }
+
+    pub fn remove(&mut self, parent: u64, name: &str) {
+        self.entries.remove(&(parent, name.to_string()));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+pub struct DirCache<T> {
+    ttl: Duration,
+    capacity: usize,
+    entries: HashMap<String, (Instant, Arc<T>)>,
+    // bumped on every invalidation, so a listing started before one is not cached.
+    epoch: u64,
+}
+
+impl<T> DirCache<T> {
+    pub fn new(ttl: Duration, capacity: usize) -> DirCache<T> {
+        DirCache {
+            ttl,
+            capacity,
+            entries: HashMap::new(),
+            epoch: 0,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn get(&mut self, path: &str) -> Option<Arc<T>> {
+        match self.entries.get(path) {
+            Some((expires, listing)) if *expires > Instant::now() => Some(listing.clone()),
+            Some(_) => {
+                self.entries.remove(path);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, path: &str, listing: Arc<T>) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        if self.entries.len() >= self.capacity {
+            self.entries.retain(|_, (expires, _)| *expires > now);
+            if self.entries.len() >= self.capacity {
+                return;
+            }
+        }
+        self.entries
+            .insert(path.to_string(), (now + self.ttl, listing));
+    }
+
+    pub fn invalidate(&mut self, path: &str) {
+        self.entries.remove(path);
+        self.epoch += 1;
+    }
+
+    pub fn invalidate_matching(&mut self, matches: impl Fn(&str) -> bool) {
+        self.entries.retain(|path, _| !matches(path));
+        self.epoch += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.epoch += 1;
+    }
+}
+
+/// Stats fetched ahead of the lookups a listing is usually followed by, each answers one.
+pub struct StatCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: HashMap<String, (Instant, Metadata)>,
+    // bumped on every invalidation, so a stat started before one is not cached.
+    epoch: u64,
+}
+
+impl StatCache {
+    pub fn new(ttl: Duration, capacity: usize) -> StatCache {
+        StatCache {
+            ttl,
+            capacity,
+            entries: HashMap::new(),
+            epoch: 0,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn take(&mut self, path: &str) -> Option<Metadata> {
+        match self.entries.remove(path) {
+            Some((expires, metadata)) if expires > Instant::now() => Some(metadata),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, path: &str, metadata: Metadata) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        if self.entries.len() >= self.capacity {
+            self.entries.retain(|_, (expires, _)| *expires > now);
+            if self.entries.len() >= self.capacity {
+                return;
+            }
+        }
+        self.entries
+            .insert(path.to_string(), (now + self.ttl, metadata));
+    }
+
+    pub fn invalidate(&mut self, path: &str) {
+        self.entries.remove(path);
+        self.epoch += 1;
+    }
+
+    pub fn invalidate_matching(&mut self, matches: impl Fn(&str) -> bool) {
+        self.entries.retain(|path, _| !matches(path));
+        self.epoch += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.epoch += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const TTL: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn test_negative_lookup_expires() {
+        let mut cache = NegativeLookupCache::new(TTL, 8);
+        cache.insert(1, "missing");
+        assert!(cache.contains(1, "missing"));
+        assert!(!cache.contains(2, "missing"));
+        sleep(TTL * 2);
+        assert!(!cache.contains(1, "missing"));
+    }
+
+    #[test]
+    fn test_negative_lookup_capacity() {
+        let mut cache = NegativeLookupCache::new(TTL, 2);
+        cache.insert(1, "a");
+        cache.insert(1, "b");
+        // a full cache drops new entries while the old ones are fresh.
+        cache.insert(1, "c");
+        assert!(cache.contains(1, "a"));
+        assert!(!cache.contains(1, "c"));
+        // and makes room once they expired.
+        sleep(TTL * 2);
+        cache.insert(1, "c");
+        assert!(cache.contains(1, "c"));
+    }
+
+    #[test]
+    fn test_negative_lookup_invalidation() {
+        let mut cache = NegativeLookupCache::new(TTL, 8);
+        cache.insert(1, "a");
+        cache.insert(1, "b");
+        cache.remove(1, "a");
+        assert!(!cache.contains(1, "a"));
+        assert!(cache.contains(1, "b"));
+        cache.clear();
+        assert!(!cache.contains(1, "b"));
+
+        let mut disabled = NegativeLookupCache::new(Duration::ZERO, 8);
+        disabled.insert(1, "a");
+        assert!(!disabled.contains(1, "a"));
+    }
+
+    #[test]
+    fn test_dir_cache_expires() {
+        let mut cache = DirCache::new(TTL, 8);
+        cache.insert("dir", Arc::new(vec!["a"]));
+        assert_eq!(*cache.get("dir").unwrap(), vec!["a"]);
+        assert!(cache.get("other").is_none());
+        sleep(TTL * 2);
+        assert!(cache.get("dir").is_none());
+    }
+
+    #[test]
+    fn test_dir_cache_capacity() {
+        let mut cache = DirCache::new(TTL, 1);
+        cache.insert("a", Arc::new(()));
+        cache.insert("b", Arc::new(()));
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        sleep(TTL * 2);
+        cache.insert("b", Arc::new(()));
+        assert!(cache.get("b").is_some());
+    }
+
+    #[test]
+    fn test_dir_cache_epoch() {
+        let mut cache = DirCache::new(TTL, 8);
+        cache.insert("a", Arc::new(()));
+        cache.insert("a/b", Arc::new(()));
+        cache.insert("c", Arc::new(()));
+        // a listing started before an invalidation sees the epoch move on.
+        let epoch = cache.epoch();
+        cache.invalidate("c");
+        assert_ne!(cache.epoch(), epoch);
+        assert!(cache.get("c").is_none());
+
+        let epoch = cache.epoch();
+        cache.invalidate_matching(|path| path == "a" || path.starts_with("a/"));
+        assert_ne!(cache.epoch(), epoch);
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("a/b").is_none());
+
+        cache.insert("d", Arc::new(()));
+        let epoch = cache.epoch();
+        cache.clear();
+        assert_ne!(cache.epoch(), epoch);
+        assert!(cache.get("d").is_none());
+    }
+
+    #[test]
+    fn test_stat_cache_taken_once() {
+        let mut cache = StatCache::new(TTL, 8);
+        cache.insert("file", Metadata::new(opendal::EntryMode::FILE));
+        assert!(cache.take("file").is_some());
+        // each prefetched stat answers one lookup.
+        assert!(cache.take("file").is_none());
+
+        cache.insert("file", Metadata::new(opendal::EntryMode::FILE));
+        sleep(TTL * 2);
+        assert!(cache.take("file").is_none());
+    }
+
+    #[test]
+    fn test_stat_cache_capacity() {
+        let mut cache = StatCache::new(TTL, 1);
+        cache.insert("a", Metadata::new(opendal::EntryMode::FILE));
+        cache.insert("b", Metadata::new(opendal::EntryMode::FILE));
+        assert!(cache.take("b").is_none());
+        assert!(cache.take("a").is_some());
+    }
+
+    #[test]
+    fn test_stat_cache_epoch() {
+        let mut cache = StatCache::new(TTL, 8);
+        cache.insert("a", Metadata::new(opendal::EntryMode::FILE));
+        cache.insert("b", Metadata::new(opendal::EntryMode::FILE));
+        let epoch = cache.epoch();
+        cache.invalidate("a");
+        assert_ne!(cache.epoch(), epoch);
+        assert!(cache.take("a").is_none());
+
+        let epoch = cache.epoch();
+        cache.invalidate_matching(|path| path == "b");
+        assert_ne!(cache.epoch(), epoch);
+        assert!(cache.take("b").is_none());
+
+        let epoch = cache.epoch();
+        cache.clear();
+        assert_ne!(cache.epoch(), epoch);
+    }
+}
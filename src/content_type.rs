@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+// content types of the extensions static sites are mostly made of.
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("mjs", "text/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain"),
+    ("md", "text/markdown"),
+    ("csv", "text/csv"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+/// Content types objects are written with, inferred from the extension of their name.
+#[derive(Clone, Debug, Default)]
+pub struct ContentTypes {
+    overrides: HashMap<String, String>,
+}
+
+impl ContentTypes {
+    /// Overrides map an extension, with or without its leading dot, to the content type used
+    /// in place of the built-in one.
+    pub fn new(overrides: impl IntoIterator<Item = (String, String)>) -> ContentTypes {
+        ContentTypes {
+            overrides: overrides
+                .into_iter()
+                .map(|(ext, content_type)| {
+                    (ext.trim_start_matches('.').to_lowercase(), content_type)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn infer(&self, path: &str) -> Option<&str> {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        // dot files such as .bashrc have no extension.
+        let (stem, ext) = name.rsplit_once('.')?;
+        if stem.is_empty() || ext.is_empty() {
+            return None;
+        }
+        let ext = ext.to_lowercase();
+        if let Some(content_type) = self.overrides.get(&ext) {
+            return Some(content_type);
+        }
+        CONTENT_TYPES
+            .iter()
+            .find(|(known, _)| *known == ext)
+            .map(|(_, content_type)| *content_type)
+    }
+}
@@ -0,0 +1,458 @@
+use std::io;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::thread;
+
+use anyhow::anyhow;
+use log::error;
+use log::info;
+use log::warn;
+use opendal::Operator;
+use vhost::vhost_user::message::VhostUserProtocolFeatures;
+use vhost::vhost_user::message::VhostUserVirtioFeatures;
+use vhost::vhost_user::Backend;
+use vhost::vhost_user::Listener;
+use vhost_user_backend::VhostUserBackend;
+use vhost_user_backend::VhostUserDaemon;
+use vhost_user_backend::VringMutex;
+use vhost_user_backend::VringState;
+use vhost_user_backend::VringT;
+use virtio_bindings::bindings::virtio_config::VIRTIO_F_VERSION_1;
+use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
+use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_INDIRECT_DESC;
+use virtio_queue::DescriptorChain;
+use virtio_queue::QueueOwnedT;
+use vm_memory::GuestAddressSpace;
+use vm_memory::GuestMemoryAtomic;
+use vm_memory::GuestMemoryLoadGuard;
+use vm_memory::GuestMemoryMmap;
+use vmm_sys_util::epoll::EventSet;
+use vmm_sys_util::eventfd::EventFd;
+
+use crate::error::*;
+use crate::filesystem::Filesystem;
+use crate::filesystem::FilesystemConfig;
+use crate::util::Reader;
+use crate::util::Writer;
+
+const HIPRIO_QUEUE_EVENT: u16 = 0;
+const REQ_QUEUE_EVENT: u16 = 1;
+const QUEUE_SIZE: usize = 1024;
+const REQUEST_QUEUES: usize = 2;
+const NUM_QUEUES: usize = REQUEST_QUEUES + 1;
+
+struct VhostUserFsThread {
+    mem: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
+    server: Filesystem,
+    event_idx: bool,
+    kill_event_fd: EventFd,
+}
+
+impl VhostUserFsThread {
+    fn new(fs: Filesystem) -> Result<VhostUserFsThread> {
+        let event_fd = EventFd::new(libc::EFD_NONBLOCK).map_err(|err| {
+            new_unexpected_error("failed to create kill eventfd", Some(err.into()))
+        })?;
+        Ok(VhostUserFsThread {
+            mem: None,
+            server: fs,
+            event_idx: false,
+            kill_event_fd: event_fd,
+        })
+    }
+
+    fn return_descriptor(
+        vring_state: &mut VringState,
+        head_index: u16,
+        event_idx: bool,
+        len: usize,
+    ) {
+        let used_len: u32 = match len.try_into() {
+            Ok(l) => l,
+            Err(_) => {
+                error!("invalid used length, can't return used descritors to the ring");
+                exit(1);
+            }
+        };
+        if vring_state.add_used(head_index, used_len).is_err() {
+            warn!("couldn't return used descriptors to the ring");
+        }
+        if event_idx {
+            match vring_state.needs_notification() {
+                Err(_) => {
+                    warn!("couldn't check if queue needs to be notified");
+                    vring_state.signal_used_queue().unwrap();
+                }
+                Ok(needs_notification) => {
+                    if needs_notification {
+                        vring_state.signal_used_queue().unwrap();
+                    }
+                }
+            }
+        } else {
+            vring_state.signal_used_queue().unwrap();
+        }
+    }
+
+    fn process_queue_serial(&self, vring_state: &mut VringState) -> Result<bool> {
+        let mut used_any = false;
+        let mem = match &self.mem {
+            Some(m) => m.memory(),
+            None => return Err(new_unexpected_error("no memory configured", None)),
+        };
+        let avail_chains: Vec<DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>> = vring_state
+            .get_queue_mut()
+            .iter(mem.clone())
+            .map_err(|_| new_unexpected_error("iterating through the queue failed", None))?
+            .collect();
+        for chain in avail_chains {
+            used_any = true;
+            let head_index = chain.head_index();
+            // a chain that can't be handled only fails its own request, so a guest sending
+            // a malformed one doesn't take the daemon down.
+            let len = match self.handle_chain(&mem, chain.clone()) {
+                Ok(len) => len,
+                Err(err) => {
+                    warn!("failed to handle descriptor chain {}: {}", head_index, err);
+                    match VhostUserFsThread::reply_chain_error(&mem, chain) {
+                        Ok(len) => len,
+                        Err(err) => {
+                            warn!(
+                                "failed to reply to descriptor chain {}: {}",
+                                head_index, err
+                            );
+                            0
+                        }
+                    }
+                }
+            };
+            VhostUserFsThread::return_descriptor(vring_state, head_index, self.event_idx, len);
+        }
+        Ok(used_any)
+    }
+
+    fn handle_chain(
+        &self,
+        mem: &GuestMemoryMmap,
+        chain: DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
+    ) -> Result<usize> {
+        let reader = Reader::new(mem, chain.clone())
+            .map_err(|_| new_unexpected_error("creating a queue reader failed", None))?;
+        let writer = Writer::new(mem, chain)
+            .map_err(|_| new_unexpected_error("creating a queue writer failed", None))?;
+        self.server
+            .handle_message(reader, writer)
+            .map_err(|_| new_unexpected_error("processing a queue writer failed", None))
+    }
+
+    /// Answers the request of a chain that failed with EIO, as far as its header is readable.
+    fn reply_chain_error(
+        mem: &GuestMemoryMmap,
+        chain: DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>,
+    ) -> Result<usize> {
+        let reader = Reader::new(mem, chain.clone())
+            .map_err(|_| new_unexpected_error("creating a queue reader failed", None))?;
+        let writer = Writer::new(mem, chain)
+            .map_err(|_| new_unexpected_error("creating a queue writer failed", None))?;
+        Filesystem::reply_failed(reader, writer)
+    }
+
+    fn handle_event_serial(&self, device_event: u16, vrings: &[VringMutex]) -> Result<()> {
+        // device events index the vrings owned by the calling worker thread.
+        let mut vring_state = match vrings.get(device_event as usize) {
+            Some(vring) => vring.get_mut(),
+            None => return Err(new_unexpected_error("failed to handle unknown event", None)),
+        };
+        if self.event_idx {
+            loop {
+                vring_state.disable_notification().unwrap();
+                self.process_queue_serial(&mut vring_state)?;
+                if !vring_state.enable_notification().unwrap() {
+                    break;
+                }
+            }
+        } else {
+            self.process_queue_serial(&mut vring_state)?;
+        }
+        Ok(())
+    }
+}
+
+struct VhostUserFsBackend {
+    thread: RwLock<VhostUserFsThread>,
+}
+
+impl VhostUserFsBackend {
+    fn new(fs: Filesystem) -> Result<VhostUserFsBackend> {
+        let thread = RwLock::new(VhostUserFsThread::new(fs)?);
+        Ok(VhostUserFsBackend { thread })
+    }
+}
+
+impl VhostUserBackend for VhostUserFsBackend {
+    type Bitmap = ();
+    type Vring = VringMutex;
+
+    fn num_queues(&self) -> usize {
+        NUM_QUEUES
+    }
+
+    fn max_queue_size(&self) -> usize {
+        QUEUE_SIZE
+    }
+
+    fn queues_per_thread(&self) -> Vec<u64> {
+        // the high priority queue shares a worker with the first request queue, every
+        // other request queue gets its own worker so requests are handled in parallel.
+        let mut queues_per_thread = vec![1 << HIPRIO_QUEUE_EVENT | 1 << REQ_QUEUE_EVENT];
+        for queue in REQ_QUEUE_EVENT as usize + 1..NUM_QUEUES {
+            queues_per_thread.push(1 << queue);
+        }
+        queues_per_thread
+    }
+
+    fn features(&self) -> u64 {
+        1 << VIRTIO_F_VERSION_1
+            | 1 << VIRTIO_RING_F_INDIRECT_DESC
+            | 1 << VIRTIO_RING_F_EVENT_IDX
+            | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits()
+    }
+
+    fn protocol_features(&self) -> VhostUserProtocolFeatures {
+        VhostUserProtocolFeatures::MQ
+            | VhostUserProtocolFeatures::BACKEND_REQ
+            | VhostUserProtocolFeatures::BACKEND_SEND_FD
+            | VhostUserProtocolFeatures::REPLY_ACK
+            | VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS
+    }
+
+    fn set_event_idx(&self, enabled: bool) {
+        self.thread.write().unwrap().event_idx = enabled;
+    }
+
+    fn update_memory(&self, mem: GuestMemoryAtomic<GuestMemoryMmap>) -> io::Result<()> {
+        self.thread.write().unwrap().mem = Some(mem);
+        Ok(())
+    }
+
+    fn handle_event(
+        &self,
+        device_event: u16,
+        evset: EventSet,
+        vrings: &[VringMutex],
+        _thread_id: usize,
+    ) -> io::Result<()> {
+        if evset != EventSet::IN {
+            return Err(new_unexpected_error(
+                "failed to handle event other than input event",
+                None,
+            )
+            .into());
+        }
+        let thread = self.thread.read().unwrap();
+        thread
+            .handle_event_serial(device_event, vrings)
+            .map_err(|err| err.into())
+    }
+
+    fn exit_event(&self, _thread_index: usize) -> Option<EventFd> {
+        Some(
+            self.thread
+                .read()
+                .unwrap()
+                .kill_event_fd
+                .try_clone()
+                .unwrap(),
+        )
+    }
+
+    fn set_backend_req_fd(&self, vu_req: Backend) {
+        self.thread.read().unwrap().server.set_backend_req(vu_req);
+    }
+}
+
+fn remove_socket(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(new_unexpected_error(
+            &format!("failed to remove socket {}", path.display()),
+            Some(err.into()),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn serve_control_socket(fs_backend: Arc<VhostUserFsBackend>, listener: UnixListener) {
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("failed to accept control connection: {}", e);
+                continue;
+            }
+        };
+        let stats = fs_backend.thread.read().unwrap().server.stats();
+        if let Err(e) = stream.write_all(stats.to_json().as_bytes()) {
+            warn!("failed to write stats: {}", e);
+        }
+    }
+}
+
+/// Sets up an ovfs daemon serving an operator to the VMM connecting on a vhost-user socket.
+pub struct OvfsBuilder {
+    operator: Operator,
+    socket_path: PathBuf,
+    config: FilesystemConfig,
+    control_socket: Option<PathBuf>,
+}
+
+impl OvfsBuilder {
+    pub fn new(
+        operator: Operator,
+        socket_path: impl Into<PathBuf>,
+        config: FilesystemConfig,
+    ) -> OvfsBuilder {
+        OvfsBuilder {
+            operator,
+            socket_path: socket_path.into(),
+            config,
+            control_socket: None,
+        }
+    }
+
+    /// Unix socket every connection to which receives the daemon's counters as JSON.
+    pub fn control_socket(mut self, path: impl Into<PathBuf>) -> OvfsBuilder {
+        self.control_socket = Some(path.into());
+        self
+    }
+
+    /// Creates the vhost-user socket, which the VMM can connect to once the daemon is started.
+    pub fn build(self) -> Result<Ovfs> {
+        let listener = Listener::new(&self.socket_path, true).map_err(|err| {
+            new_unexpected_error("failed to create vhost-user socket", Some(err.into()))
+        })?;
+        let fs = Filesystem::new(self.operator, self.config);
+        let fs_backend = Arc::new(VhostUserFsBackend::new(fs)?);
+        let daemon = VhostUserDaemon::new(
+            String::from("ovfs-backend"),
+            fs_backend.clone(),
+            GuestMemoryAtomic::new(GuestMemoryMmap::new()),
+        )
+        .map_err(|err| {
+            new_unexpected_error("failed to create daemon", Some(anyhow!("{:?}", err)))
+        })?;
+
+        if let Some(control_socket) = self.control_socket.as_ref() {
+            remove_socket(control_socket)?;
+            let listener = UnixListener::bind(control_socket).map_err(|err| {
+                new_unexpected_error("failed to bind control socket", Some(err.into()))
+            })?;
+            let control_backend = fs_backend.clone();
+            thread::spawn(move || serve_control_socket(control_backend, listener));
+        }
+
+        Ok(Ovfs {
+            daemon,
+            listener: Some(listener),
+            handle: OvfsHandle {
+                fs_backend,
+                socket_path: self.socket_path,
+                control_socket: self.control_socket,
+            },
+        })
+    }
+}
+
+/// Daemon built by `OvfsBuilder`.
+pub struct Ovfs {
+    daemon: VhostUserDaemon<Arc<VhostUserFsBackend>>,
+    // taken once the daemon is started.
+    listener: Option<Listener>,
+    handle: OvfsHandle,
+}
+
+impl Ovfs {
+    /// Handle that refreshes or shuts down the daemon from other threads.
+    pub fn handle(&self) -> OvfsHandle {
+        self.handle.clone()
+    }
+
+    /// Waits for the VMM to connect, then serves it on the daemon's worker threads.
+    pub fn start(&mut self) -> Result<()> {
+        let listener = self
+            .listener
+            .take()
+            .ok_or(new_unexpected_error("daemon is already started", None))?;
+        self.daemon.start(listener).map_err(|err| {
+            new_unexpected_error("failed to start daemon", Some(anyhow!("{:?}", err)))
+        })
+    }
+
+    /// Blocks until the VMM disconnects.
+    pub fn wait(&mut self) -> Result<()> {
+        self.daemon.wait().map_err(|err| {
+            new_unexpected_error("failed to wait for daemon", Some(anyhow!("{:?}", err)))
+        })
+    }
+
+    pub fn shutdown(&self) -> Result<()> {
+        self.handle.shutdown()
+    }
+}
+
+#[derive(Clone)]
+pub struct OvfsHandle {
+    fs_backend: Arc<VhostUserFsBackend>,
+    socket_path: PathBuf,
+    control_socket: Option<PathBuf>,
+}
+
+impl OvfsHandle {
+    /// Serves later requests from a new operator, e.g. one built with rotated credentials.
+    pub fn refresh_operator(&self, operator: Operator) {
+        self.fs_backend
+            .thread
+            .read()
+            .unwrap()
+            .server
+            .refresh_operator(operator);
+    }
+
+    /// Stops the workers, closes the files still open for writing and removes the sockets.
+    pub fn shutdown(&self) -> Result<()> {
+        let kill_event_fd = self
+            .fs_backend
+            .thread
+            .read()
+            .unwrap()
+            .kill_event_fd
+            .try_clone()
+            .map_err(|err| {
+                new_unexpected_error("failed to clone kill eventfd", Some(err.into()))
+            })?;
+        kill_event_fd.write(1).map_err(|err| {
+            new_unexpected_error("failed to shutdown worker thread", Some(err.into()))
+        })?;
+        // taking the write lock waits for the requests still being handled by the workers.
+        drop(self.fs_backend.thread.write().unwrap());
+        let thread = self.fs_backend.thread.read().unwrap();
+        thread.server.close_writers()?;
+        for metrics in thread.server.metrics_snapshot() {
+            info!(
+                "opcode {}: calls={} errors={} latency={}",
+                metrics.opcode,
+                metrics.calls,
+                metrics.errors,
+                metrics.latency_histogram()
+            );
+        }
+        if let Some(control_socket) = self.control_socket.as_ref() {
+            remove_socket(control_socket)?;
+        }
+        remove_socket(&self.socket_path)
+    }
+}
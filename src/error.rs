@@ -2,16 +2,19 @@ use std::ffi::CStr;
 use std::io;
 
 use anyhow::Error as AnyError;
-use log::debug;
+use log::warn;
 use opendal::ErrorKind;
 use snafu::prelude::Snafu;
 
+use crate::metrics;
+
 #[derive(Debug, Snafu)]
 #[non_exhaustive]
 pub enum Error {
     #[snafu(display("Vhost user fs error: {}, source: {:?}", message, source))]
     VhostUserFsError {
         message: String,
+        errno: Option<libc::c_int>,
         #[snafu(source(false))]
         source: Option<AnyError>,
     },
@@ -23,9 +26,77 @@ pub enum Error {
     },
 }
 
+impl Error {
+    /// Errno the error was created from, if any.
+    pub fn errno(&self) -> Option<libc::c_int> {
+        match self {
+            Error::VhostUserFsError { errno, .. } => *errno,
+            _ => None,
+        }
+    }
+}
+
+/// Errno of a full disk or an expired request carried by the io errors an OpenDAL error was
+/// built from, e.g. by local services or the HTTP client.
+fn source_errno(error: &opendal::Error) -> Option<libc::c_int> {
+    let mut source = std::error::Error::source(error);
+    while let Some(err) = source {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            match err.raw_os_error() {
+                Some(errno @ (libc::ENOSPC | libc::EDQUOT | libc::ETIMEDOUT)) => {
+                    return Some(errno)
+                }
+                _ if err.kind() == io::ErrorKind::TimedOut => return Some(libc::ETIMEDOUT),
+                _ => {}
+            }
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Texts of a full backend or an exceeded quota in the messages OpenDAL builds from service
+/// responses, it has no error kind for either. They hold the service's error code, or the
+/// response status for services without one.
+///
+/// The texts were checked against OpenDAL 0.49.1, the tests fail when an upgrade changes them.
+const MESSAGE_ERRNOS: &[(&str, libc::c_int)] = &[
+    // s3 error code.
+    ("QuotaExceeded", libc::EDQUOT),
+    // gcs error reason.
+    ("quotaExceeded", libc::EDQUOT),
+    // error code of s3 compatible servers such as MinIO, e.g. XMinioStorageFull.
+    ("StorageFull", libc::ENOSPC),
+    // 507 Insufficient Storage in the response context of any http service.
+    ("status: 507", libc::ENOSPC),
+];
+
+fn message_errno(error: &opendal::Error) -> Option<libc::c_int> {
+    let message = error.to_string();
+    MESSAGE_ERRNOS
+        .iter()
+        .find(|(text, _)| message.contains(text))
+        .map(|(_, errno)| *errno)
+}
+
+/// The timeout layer fails with a temporary unexpected error. ovfs puts it above the retry
+/// layer, which marks every error it gives up on as persistent, so a temporary error
+/// reaching ovfs can only be a timeout. The guest's request has to fail rather than be
+/// retried.
+fn is_timeout(error: &opendal::Error) -> bool {
+    error.kind() == ErrorKind::Unexpected && error.is_temporary()
+}
+
 impl From<opendal::Error> for Error {
     fn from(error: opendal::Error) -> Error {
-        debug!("opendal error occurred: {:?}", error);
+        warn!("opendal error occurred: {}", error);
+        metrics::record_backend_error(error.kind().into_static());
+        if let Some(errno) = source_errno(&error).or_else(|| message_errno(&error)) {
+            return Error::from(errno);
+        }
+        if is_timeout(&error) {
+            return Error::from(libc::ETIMEDOUT);
+        }
         match error.kind() {
             ErrorKind::Unsupported => Error::from(libc::EOPNOTSUPP),
             ErrorKind::IsADirectory => Error::from(libc::EISDIR),
@@ -35,7 +106,9 @@ impl From<opendal::Error> for Error {
             ErrorKind::NotADirectory => Error::from(libc::ENOTDIR),
             ErrorKind::RangeNotSatisfied => Error::from(libc::EINVAL),
             ErrorKind::RateLimited => Error::from(libc::EBUSY),
-            _ => Error::from(libc::ENOENT),
+            ErrorKind::ConfigInvalid => Error::from(libc::EINVAL),
+            ErrorKind::IsSameFile => Error::from(libc::EINVAL),
+            _ => Error::from(libc::EIO),
         }
     }
 }
@@ -51,6 +124,7 @@ impl From<libc::c_int> for Error {
         };
         Error::VhostUserFsError {
             message,
+            errno: Some(errno),
             source: None,
         }
     }
@@ -59,24 +133,20 @@ impl From<libc::c_int> for Error {
 impl From<Error> for io::Error {
     fn from(error: Error) -> io::Error {
         match error {
-            Error::VhostUserFsError { message, source } => {
+            Error::VhostUserFsError {
+                message, source, ..
+            } => {
                 let message = format!("Vhost user fs error: {}", message);
                 match source {
-                    Some(source) => io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("{}, source: {:?}", message, source),
-                    ),
-                    None => io::Error::new(io::ErrorKind::Other, message),
+                    Some(source) => io::Error::other(format!("{}, source: {:?}", message, source)),
+                    None => io::Error::other(message),
                 }
             }
             Error::Unexpected { message, source } => {
                 let message = format!("Unexpected error: {}", message);
                 match source {
-                    Some(source) => io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("{}, source: {:?}", message, source),
-                    ),
-                    None => io::Error::new(io::ErrorKind::Other, message),
+                    Some(source) => io::Error::other(format!("{}, source: {:?}", message, source)),
+                    None => io::Error::other(message),
                 }
             }
         }
@@ -88,6 +158,7 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub fn new_vhost_user_fs_error(message: &str, source: Option<AnyError>) -> Error {
     Error::VhostUserFsError {
         message: message.to_string(),
+        errno: None,
         source,
     }
 }
@@ -98,3 +169,132 @@ pub fn new_unexpected_error(message: &str, source: Option<AnyError>) -> Error {
         source,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use opendal::services::Gcs;
+    use opendal::services::S3;
+    use opendal::Operator;
+
+    use super::*;
+
+    fn errno(error: opendal::Error) -> Option<libc::c_int> {
+        Error::from(error).errno()
+    }
+
+    // answers every request with the given status and body, returning the endpoint.
+    fn serve(status: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        endpoint
+    }
+
+    fn s3(endpoint: &str) -> Operator {
+        let builder = S3::default()
+            .bucket("ovfs")
+            .endpoint(endpoint)
+            .region("us-east-1")
+            .access_key_id("ovfs")
+            .secret_access_key("ovfs")
+            .disable_config_load()
+            .disable_ec2_metadata();
+        Operator::new(builder).unwrap().finish()
+    }
+
+    // writes, which is what fills a backend, and whose response carries an error body.
+    fn write_errno(operator: Operator) -> Option<libc::c_int> {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let err = rt.block_on(operator.write("file", "data")).unwrap_err();
+        errno(err)
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        for (kind, expected) in [
+            (ErrorKind::NotFound, libc::ENOENT),
+            (ErrorKind::PermissionDenied, libc::EACCES),
+            (ErrorKind::AlreadyExists, libc::EEXIST),
+            (ErrorKind::ConfigInvalid, libc::EINVAL),
+            (ErrorKind::RateLimited, libc::EBUSY),
+            (ErrorKind::Unsupported, libc::EOPNOTSUPP),
+            (ErrorKind::Unexpected, libc::EIO),
+        ] {
+            assert_eq!(errno(opendal::Error::new(kind, "error")), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let timeout = opendal::Error::new(ErrorKind::Unexpected, "timeout").set_temporary();
+        assert_eq!(errno(timeout), Some(libc::ETIMEDOUT));
+        // given up on by the retry layer.
+        let retried = opendal::Error::new(ErrorKind::Unexpected, "error").set_persistent();
+        assert_eq!(errno(retried), Some(libc::EIO));
+    }
+
+    #[test]
+    fn test_source_errno() {
+        let full = opendal::Error::new(ErrorKind::Unexpected, "error")
+            .set_source(io::Error::from_raw_os_error(libc::ENOSPC));
+        assert_eq!(errno(full), Some(libc::ENOSPC));
+        let timed_out = opendal::Error::new(ErrorKind::Unexpected, "error")
+            .set_source(io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(errno(timed_out), Some(libc::ETIMEDOUT));
+    }
+
+    #[test]
+    fn test_s3_quota_exceeded() {
+        let body = "<Error><Code>QuotaExceeded</Code><Message>quota</Message></Error>";
+        let endpoint = serve("403 Forbidden", body);
+        assert_eq!(write_errno(s3(&endpoint)), Some(libc::EDQUOT));
+    }
+
+    #[test]
+    fn test_gcs_quota_exceeded() {
+        let body =
+            r#"{"error":{"code":403,"message":"quota","errors":[{"reason":"quotaExceeded"}]}}"#;
+        let endpoint = serve("403 Forbidden", body);
+        let builder = Gcs::default()
+            .bucket("ovfs")
+            .endpoint(&endpoint)
+            .allow_anonymous()
+            .disable_vm_metadata()
+            .disable_config_load();
+        let operator = Operator::new(builder).unwrap().finish();
+        assert_eq!(write_errno(operator), Some(libc::EDQUOT));
+    }
+
+    #[test]
+    fn test_storage_full() {
+        let body = "<Error><Code>XMinioStorageFull</Code><Message>full</Message></Error>";
+        let endpoint = serve("400 Bad Request", body);
+        assert_eq!(write_errno(s3(&endpoint)), Some(libc::ENOSPC));
+    }
+
+    #[test]
+    fn test_insufficient_storage() {
+        let endpoint = serve("507 Insufficient Storage", "");
+        assert_eq!(write_errno(s3(&endpoint)), Some(libc::ENOSPC));
+    }
+}
@@ -1,62 +1,208 @@
+use std::cmp::max;
+use std::cmp::min;
 use std::collections::HashMap;
-use std::ffi::CStr;
+use std::collections::VecDeque;
+use std::fs::set_permissions;
+use std::fs::symlink_metadata;
+use std::fs::File;
+use std::fs::Permissions;
+use std::io;
 use std::io::Read;
 use std::io::Write;
 use std::mem::size_of;
+use std::ops::Range;
+use std::os::fd::FromRawFd;
+use std::os::unix::fs::FileExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::RwLock;
 use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
+use futures::future::join_all;
+use futures::TryStreamExt;
 use log::debug;
+use log::info;
+use log::warn;
+use md5::Digest;
+use md5::Md5;
 use opendal::Buffer;
+use opendal::ErrorKind;
+use opendal::Metakey;
 use opendal::Operator;
+use sharded_slab::Entry;
 use sharded_slab::Slab;
 use tokio::runtime::Builder;
 use tokio::runtime::Runtime;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::Semaphore;
+use vhost::vhost_user::message::VhostUserFSBackendMsg;
+use vhost::vhost_user::message::VhostUserFSBackendMsgFlags;
+use vhost::vhost_user::message::VHOST_USER_FS_BACKEND_ENTRIES;
+use vhost::vhost_user::Backend;
+use vhost::vhost_user::VhostUserFrontendReqHandler;
 use vm_memory::ByteValued;
 
 use crate::buffer::BufferWrapper;
+use crate::cache::DirCache;
+use crate::cache::NegativeLookupCache;
+use crate::cache::ReadCache;
+use crate::cache::StatCache;
+use crate::compression::Compression;
+use crate::content_type::ContentTypes;
 use crate::error::*;
 use crate::filesystem_message::*;
+use crate::metrics;
+use crate::metrics::Metrics;
+use crate::metrics::OpcodeSnapshot;
+use crate::metrics::Stats;
 use crate::util::Reader;
 use crate::util::Writer;
 
 const KERNEL_VERSION: u32 = 7;
 const KERNEL_MINOR_VERSION: u32 = 38;
 const MIN_KERNEL_MINOR_VERSION: u32 = 27;
+// minor versions that introduced the init flags a guest may ask for.
+const MAX_PAGES_MINOR_VERSION: u32 = 28;
+const MAP_ALIGNMENT_MINOR_VERSION: u32 = 31;
+const KILLPRIV_V2_MINOR_VERSION: u32 = 33;
 const BUFFER_HEADER_SIZE: u32 = 4096;
-const MAX_BUFFER_SIZE: u32 = 1 << 20;
-const DEFAULT_TTL: Duration = Duration::from_secs(1);
-const DEFAULT_GID: u32 = 1000;
-const DEFAULT_UID: u32 = 1000;
+const PAGE_SIZE: u32 = 4096;
+// st_blocks is always counted in 512 byte units.
+const STAT_BLOCK_SIZE: u64 = 512;
 const DEFAULT_DIR_NLINK: u32 = 2;
 const DEFAULT_FILE_NLINK: u32 = 1;
 const DEFAULT_MODE: u32 = 0o755;
+const DEFAULT_SYMLINK_MODE: u32 = 0o777;
+const PERMISSION_MODE_MASK: u32 = 0o7777;
 const DEFAULT_ROOT_DIR_INODE: u64 = 1;
 const DEAFULT_DIR_TYPE_IN_DIR_ENTRY: u32 = 4;
 const DEAFULT_FILE_TYPE_IN_DIR_ENTRY: u32 = 8;
 const DIRENT_PADDING: [u8; 8] = [0; 8];
+const STATFS_BLOCK_SIZE: u32 = 4096;
+// longest entry name accepted, also reported by statfs.
+const MAX_NAME_LEN: u32 = 255;
+const STATFS_DEFAULT_BLOCKS: u64 = 1 << 32;
+const STATFS_DEFAULT_FILES: u64 = 1 << 32;
+const SYMLINK_METADATA_KEY: &str = "ovfs-symlink";
+const MODE_METADATA_KEY: &str = "ovfs-mode";
+const UID_METADATA_KEY: &str = "ovfs-uid";
+const GID_METADATA_KEY: &str = "ovfs-gid";
+const COMPRESSION_METADATA_KEY: &str = "ovfs-compression";
+// size of a compressed file, the object itself only knows its compressed length.
+const SIZE_METADATA_KEY: &str = "ovfs-size";
+// extended attribute holding the backend's ETag of a file.
+const ETAG_XATTR_NAME: &str = "user.ovfs.etag";
+const READ_CACHE_ENTRIES: usize = 16;
+const NEGATIVE_LOOKUP_ENTRIES: usize = 4096;
+const DIR_CACHE_ENTRIES: usize = 256;
+const STAT_CACHE_ENTRIES: usize = 4096;
+const INODE_EVICTION_SCAN_LIMIT: usize = 64;
+const DIR_CACHE_MAX_LISTING: usize = 4096;
+// ioctls answered on any open file, _IOR('O', 1, char[8192]) and _IO('O', 2). the first
+// fills the buffer with the NUL terminated stats JSON, the second closes every writer and
+// returns how many there were.
+const OVFS_IOC_STATS: u32 = 0xa000_4f01;
+const OVFS_IOC_FLUSH_ALL: u32 = 0x4f02;
 
 enum FileType {
     Dir,
     File,
+    Symlink,
 }
 
 struct InnerWriter {
-    writer: opendal::Writer,
+    path: String,
+    // compressed files have no streaming writer, they are kept whole in `pending` and
+    // written once closed.
+    writer: Option<opendal::Writer>,
     written: u64,
+    // sequential writes not yet handed to the writer, they end at `written`.
+    pending: Vec<u8>,
+    // closing replaces the whole object, so a handle that never wrote must leave it alone.
+    dirty: bool,
+    // appending writers add every write at the end, whatever offset the guest saw.
+    append: bool,
+    // the writer adds to the object instead of replacing it, so an unused one is closed
+    // rather than aborted, which not every backend supports.
+    appends_object: bool,
+    // hash of everything handed to the writer, compared with the object once it is closed.
+    digest: Option<Md5>,
+}
+
+impl InnerWriter {
+    async fn flush(&mut self) -> opendal::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Ok(());
+        };
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        if let Some(digest) = self.digest.as_mut() {
+            digest.update(&pending);
+        }
+        writer.write(pending).await
+    }
+
+    async fn close(&mut self) -> opendal::Result<()> {
+        if !self.dirty {
+            return match self.writer.as_mut() {
+                Some(writer) if self.appends_object => writer.close().await,
+                Some(writer) => writer.abort().await,
+                None => Ok(()),
+            };
+        }
+        let result = match self.flush().await {
+            Ok(()) => match self.writer.as_mut() {
+                Some(writer) => writer.close().await,
+                None => Ok(()),
+            },
+            Err(err) => Err(err),
+        };
+        // a writer that failed to commit is given up, aborting it so a multipart upload isn't
+        // left behind on the backend.
+        if let (Err(err), Some(writer)) = (&result, self.writer.as_mut()) {
+            warn!(
+                "failed to close writer of {}, aborting it: {}",
+                self.path, err
+            );
+            if let Err(err) = writer.abort().await {
+                warn!("failed to abort writer of {}: {}", self.path, err);
+            }
+        }
+        result
+    }
 }
 
 #[derive(Clone)]
 struct OpenedFile {
     path: String,
     metadata: Attr,
+    nlookup: u64,
+    generation: u64,
+    // owner set by chown, reported instead of the configured one.
+    owner: Option<(u32, u32)>,
+    // handles opened on the inode, which pin it in the inode cache.
+    opens: u64,
+    // looked up again since the eviction scan last passed it.
+    recently_used: bool,
+    // codec the object is stored with, its contents can only be read whole.
+    compression: Option<Compression>,
 }
 
 impl OpenedFile {
     fn new(file_type: FileType, path: &str) -> OpenedFile {
         let mut attr: Attr = unsafe { std::mem::zeroed() };
-        attr.uid = DEFAULT_UID;
-        attr.gid = DEFAULT_GID;
+        attr.blksize = STATFS_BLOCK_SIZE;
         match file_type {
             FileType::Dir => {
                 attr.nlink = DEFAULT_DIR_NLINK;
@@ -66,14 +212,56 @@ impl OpenedFile {
                 attr.nlink = DEFAULT_FILE_NLINK;
                 attr.mode = libc::S_IFREG | DEFAULT_MODE;
             }
+            FileType::Symlink => {
+                attr.nlink = DEFAULT_FILE_NLINK;
+                attr.mode = libc::S_IFLNK | DEFAULT_SYMLINK_MODE;
+            }
         }
         OpenedFile {
             path: path.to_string(),
             metadata: attr,
+            nlookup: 0,
+            generation: 0,
+            owner: None,
+            opens: 0,
+            recently_used: true,
+            compression: None,
+        }
+    }
+
+    fn set_size(&mut self, size: u64) {
+        self.metadata.size = size;
+        self.metadata.blocks = size.div_ceil(STAT_BLOCK_SIZE);
+    }
+
+    fn set_times(&mut self, since_epoch: Duration) {
+        self.metadata.atime = since_epoch.as_secs();
+        self.metadata.mtime = since_epoch.as_secs();
+        self.metadata.ctime = since_epoch.as_secs();
+        self.metadata.atimensec = since_epoch.subsec_nanos();
+        self.metadata.mtimensec = since_epoch.subsec_nanos();
+        self.metadata.ctimensec = since_epoch.subsec_nanos();
+    }
+
+    /// Clears the setuid bit, and the setgid bit of group executable files, as a write by an
+    /// unprivileged process does on a local file system.
+    fn kill_suidgid(&mut self) {
+        let mut mode = self.metadata.mode & !libc::S_ISUID;
+        if mode & libc::S_IXGRP != 0 {
+            mode &= !libc::S_ISGID;
+        }
+        self.metadata.mode = mode;
+    }
+
+    fn set_permissions(&mut self, mode: u32) {
+        if self.metadata.mode & libc::S_IFMT == libc::S_IFLNK {
+            return;
         }
+        self.metadata.mode = (self.metadata.mode & libc::S_IFMT) | (mode & PERMISSION_MODE_MASK);
     }
 }
 
+#[derive(Clone)]
 struct DirEntry {
     ino: u64,
     off: u64,
@@ -81,144 +269,562 @@ struct DirEntry {
     name: String,
 }
 
+/// Directory listing pulled from the backend one readdir page at a time.
+struct DirStream {
+    // directory being listed, as its inode path.
+    path: String,
+    lister: opendal::Lister,
+    // entries taken from the lister so far, the offset of the last one.
+    listed: u64,
+    // entry that did not fit into the previous reply.
+    pending: Option<DirEntry>,
+    // services list in key order, so names folded from deeper keys follow each other.
+    last_name: Option<String>,
+    // entries kept for the directory cache until the listing grows too large for it.
+    collected: Option<Vec<DirEntry>>,
+    // directory cache epoch when the listing started, later changes keep it out of the cache.
+    cache_epoch: u64,
+}
+
+impl DirStream {
+    /// Offset of the next entry returned to the guest.
+    fn position(&self) -> u64 {
+        self.listed - self.pending.is_some() as u64
+    }
+}
+
+enum DirListing {
+    Cached(Arc<Vec<DirEntry>>),
+    Stream(Box<DirStream>),
+}
+
+struct OpenedHandle {
+    inode: u64,
+    flags: u32,
+    // listing that later readdir pages resume from.
+    listing: Option<DirListing>,
+}
+
+pub struct FilesystemConfig {
+    /// Number of worker threads of the runtime driving backend requests.
+    pub worker_threads: usize,
+    /// Size in bytes fetched ahead on each backend read, 0 disables read-ahead.
+    pub read_ahead_size: u64,
+    /// How long a failed lookup is answered without asking the backend.
+    pub negative_lookup_ttl: Duration,
+    /// How long a directory listing is reused before listing the backend again.
+    pub dir_cache_ttl: Duration,
+    /// How long the guest kernel may cache name lookups.
+    pub entry_timeout: Duration,
+    /// How long the guest kernel may cache attributes.
+    pub attr_timeout: Duration,
+    /// Bytes of sequential writes gathered before they are passed to the backend writer.
+    pub write_buffer_size: usize,
+    /// Largest read or write payload in bytes, advertised to the guest on init.
+    pub max_buffer_size: u32,
+    /// Rejects every request that would modify the backend.
+    pub read_only: bool,
+    /// Serves reads by mapping file contents into the DAX window when the guest supports it.
+    pub dax: bool,
+    /// Lets the guest kernel cache writes and send them in larger batches.
+    pub writeback: bool,
+    /// Checks every object written through a file handle against what the guest wrote.
+    pub verify_writes: bool,
+    /// Compresses files before they are stored, on backends keeping user metadata.
+    pub compression: Option<Compression>,
+    /// Writes objects with a content type inferred from their name, on backends storing one.
+    pub content_types: Option<ContentTypes>,
+    /// Lists directories on stat to report 2 plus their subdirectory count as nlink.
+    pub count_subdirs: bool,
+    /// Returns attributes along with directory entries, sparing the guest a lookup for each.
+    pub readdirplus: bool,
+    /// Looks a name missing in its exact case up among the entries of its directory.
+    pub case_insensitive: bool,
+    /// Owner reported for every file.
+    pub uid: u32,
+    pub gid: u32,
+    /// Reports every file as owned by the process asking for it instead.
+    pub passthrough_owner: bool,
+    /// Inodes cached before ones the guest no longer references are evicted, 0 keeps all.
+    pub max_inodes: usize,
+    /// Largest size a file is grown to by rewriting it in memory, larger ones fail with EFBIG.
+    pub max_file_size: u64,
+    /// Entries of a listed page stated at once ahead of the guest's lookups, 0 disables it.
+    pub prefetch_stats: usize,
+}
+
 pub struct Filesystem {
     rt: Runtime,
-    core: Operator,
-    opened_files: Slab<OpenedFile>,
+    core: RwLock<Operator>,
+    opened_files: Slab<RwLock<OpenedFile>>,
+    // slab key of the root directory, the guest always knows it as the root inode.
+    root_slab_key: AtomicUsize,
     opened_files_map: Mutex<HashMap<String, u64>>,
-    opened_files_writer: Mutex<HashMap<String, InnerWriter>>,
+    // streaming writers keyed by the file handle returned from open and create.
+    opened_files_writer: AsyncMutex<HashMap<u64, InnerWriter>>,
+    // handles returned from open, create and opendir, the slab key is the handle.
+    opened_handles: Slab<RwLock<OpenedHandle>>,
+    // the slab can't count its entries.
+    opened_handles_count: AtomicU64,
+    // inodes in the order they were allocated, scanned from the front for eviction.
+    inode_lru: Mutex<VecDeque<u64>>,
+    max_inodes: usize,
+    max_file_size: u64,
+    prefetch_stats: usize,
+    read_ahead_size: u64,
+    entry_timeout: Duration,
+    attr_timeout: Duration,
+    max_buffer_size: u32,
+    write_buffer_size: usize,
+    read_only: bool,
+    dax: bool,
+    writeback: bool,
+    verify_writes: bool,
+    compression: Option<Compression>,
+    content_types: Option<ContentTypes>,
+    count_subdirs: bool,
+    readdirplus: bool,
+    case_insensitive: bool,
+    uid: u32,
+    gid: u32,
+    passthrough_owner: bool,
+    next_generation: AtomicU64,
+    backend_req: Mutex<Option<Backend>>,
+    read_cache: Mutex<ReadCache>,
+    negative_lookups: Mutex<NegativeLookupCache>,
+    dir_cache: Mutex<DirCache<Vec<DirEntry>>>,
+    stat_cache: Mutex<StatCache>,
+    // contents of files unlinked while still open, by inode, dropped with their last handle.
+    orphans: Mutex<HashMap<u64, Vec<u8>>>,
+    metrics: Metrics,
 }
 
 impl Filesystem {
-    pub fn new(core: Operator) -> Filesystem {
+    pub fn new(core: Operator, config: FilesystemConfig) -> Filesystem {
         let rt = Builder::new_multi_thread()
-            .worker_threads(4)
+            .worker_threads(config.worker_threads)
             .enable_all()
             .build()
             .unwrap();
 
         Filesystem {
             rt,
-            core,
+            core: RwLock::new(core),
             opened_files: Slab::new(),
+            root_slab_key: AtomicUsize::new(0),
             opened_files_map: Mutex::new(HashMap::new()),
-            opened_files_writer: Mutex::new(HashMap::new()),
+            opened_files_writer: AsyncMutex::new(HashMap::new()),
+            opened_handles: Slab::new(),
+            opened_handles_count: AtomicU64::new(0),
+            inode_lru: Mutex::new(VecDeque::new()),
+            max_inodes: config.max_inodes,
+            max_file_size: config.max_file_size,
+            prefetch_stats: config.prefetch_stats,
+            read_ahead_size: config.read_ahead_size,
+            entry_timeout: config.entry_timeout,
+            attr_timeout: config.attr_timeout,
+            max_buffer_size: config.max_buffer_size,
+            write_buffer_size: config.write_buffer_size,
+            read_only: config.read_only,
+            dax: config.dax,
+            writeback: config.writeback,
+            verify_writes: config.verify_writes,
+            compression: config.compression,
+            content_types: config.content_types,
+            count_subdirs: config.count_subdirs,
+            readdirplus: config.readdirplus,
+            case_insensitive: config.case_insensitive,
+            uid: config.uid,
+            gid: config.gid,
+            passthrough_owner: config.passthrough_owner,
+            next_generation: AtomicU64::new(1),
+            backend_req: Mutex::new(None),
+            read_cache: Mutex::new(ReadCache::new(READ_CACHE_ENTRIES)),
+            negative_lookups: Mutex::new(NegativeLookupCache::new(
+                config.negative_lookup_ttl,
+                NEGATIVE_LOOKUP_ENTRIES,
+            )),
+            dir_cache: Mutex::new(DirCache::new(config.dir_cache_ttl, DIR_CACHE_ENTRIES)),
+            // a prefetched stat is as fresh as the attributes the guest caches anyway.
+            stat_cache: Mutex::new(StatCache::new(config.attr_timeout, STAT_CACHE_ENTRIES)),
+            orphans: Mutex::new(HashMap::new()),
+            metrics: Metrics::new(),
         }
     }
 
     pub fn handle_message(&self, mut r: Reader, w: Writer) -> Result<usize> {
-        let in_header: InHeader = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
-        if in_header.len > (MAX_BUFFER_SIZE + BUFFER_HEADER_SIZE) {
+        // requests are decoded from a copy, so the guest can't change them while they are checked.
+        let mut buf = Vec::with_capacity(r.available_bytes());
+        r.read_to_end(&mut buf)
+            .map_err(|_| Error::from(libc::EIO))?;
+        let in_header: InHeader = read_obj(&buf).ok_or(Error::from(libc::EIO))?;
+        metrics::take_error();
+        let start = Instant::now();
+        let result = self.dispatch_message(in_header, &mut buf, w);
+        let failed = metrics::take_error() || result.is_err();
+        self.metrics
+            .record(in_header.opcode, start.elapsed(), failed);
+        result
+    }
+
+    /// Replaces the backend operator, e.g. after its credentials were rotated.
+    ///
+    /// Requests already running keep the operator they started with.
+    pub fn refresh_operator(&self, core: Operator) {
+        *self.core.write().unwrap() = core;
+    }
+
+    /// Keeps the channel used to ask the frontend to map regions into the DAX window.
+    pub fn set_backend_req(&self, backend_req: Backend) {
+        *self.backend_req.lock().unwrap() = Some(backend_req);
+    }
+
+    pub fn metrics_snapshot(&self) -> Vec<OpcodeSnapshot> {
+        self.metrics.snapshot()
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            opcodes: self.metrics.snapshot(),
+            inodes: self.opened_files_map.lock().unwrap().len(),
+            handles: self.opened_handles_count.load(Ordering::Relaxed),
+            writers: self.rt.block_on(self.opened_files_writer.lock()).len(),
+            backend_errors: metrics::backend_errors(),
+        }
+    }
+
+    fn dispatch_message(&self, in_header: InHeader, buf: &mut [u8], w: Writer) -> Result<usize> {
+        if in_header.len > (self.max_buffer_size + BUFFER_HEADER_SIZE) {
             return Filesystem::reply_error(in_header.unique, w, libc::EIO);
         }
-        if let Ok(opcode) = Opcode::try_from(in_header.opcode) {
-            debug!(
-                "received request: opcode={}, inode={}",
-                in_header.opcode, in_header.nodeid
-            );
-            match opcode {
-                Opcode::Init => self.init(in_header, r, w),
-                Opcode::Destroy => self.destory(),
-                Opcode::Forget => self.forget(in_header),
-                Opcode::Lookup => self.lookup(in_header, r, w),
-                Opcode::Getattr => self.getattr(in_header, r, w),
-                Opcode::Setattr => self.setattr(in_header, r, w),
-                Opcode::Create => self.create(in_header, r, w),
-                Opcode::Unlink => self.unlink(in_header, r, w),
-                Opcode::Release => self.release(in_header, r, w),
-                Opcode::Flush => self.flush(in_header, r, w),
-                Opcode::Open => self.open(in_header, r, w),
-                Opcode::Read => self.read(in_header, r, w),
-                Opcode::Write => self.write(in_header, r, w),
-                Opcode::Mkdir => self.mkdir(in_header, r, w),
-                Opcode::Rmdir => self.rmdir(in_header, r, w),
-                Opcode::Releasedir => self.releasedir(in_header, r, w),
-                Opcode::Fsyncdir => self.fsyncdir(in_header, r, w),
-                Opcode::Opendir => self.opendir(in_header, r, w),
-                Opcode::Readdir => self.readdir(in_header, r, w),
+        let opcode = match decode_request(buf) {
+            Ok(request) => request.opcode,
+            Err(err) if err.errno() == Some(libc::ENOSYS) => {
+                debug!(
+                    "received unknown request: opcode={}, inode={}",
+                    in_header.opcode, in_header.nodeid
+                );
+                return Filesystem::reply_error(in_header.unique, w, libc::ENOSYS);
             }
-        } else {
-            debug!(
-                "received unknown request: opcode={}, inode={}",
-                in_header.opcode, in_header.nodeid
-            );
-            Filesystem::reply_error(in_header.unique, w, libc::ENOSYS)
+            Err(err) => {
+                warn!(
+                    "received malformed request: opcode={}, len={}, received={}",
+                    in_header.opcode,
+                    in_header.len,
+                    buf.len()
+                );
+                // forgets and interrupts are dropped as they have no reply to fail.
+                if Opcode::try_from(in_header.opcode).is_ok_and(|opcode| !opcode.has_reply()) {
+                    return Ok(0);
+                }
+                let errno = err.errno().unwrap_or(libc::EIO);
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
+        };
+        debug!(
+            "received request: opcode={}, inode={}",
+            in_header.opcode, in_header.nodeid
+        );
+        if self.read_only && Filesystem::is_mutating(&opcode) {
+            return Filesystem::reply_error(in_header.unique, w, libc::EROFS);
+        }
+        let r = Reader::from_bytes(&mut buf[size_of::<InHeader>()..]);
+        self.dispatch_opcode(opcode, in_header, r, w)
+    }
+
+    fn dispatch_opcode(
+        &self,
+        opcode: Opcode,
+        in_header: InHeader,
+        r: Reader,
+        w: Writer,
+    ) -> Result<usize> {
+        match opcode {
+            Opcode::Init => self.init(in_header, r, w),
+            Opcode::Destroy => self.destroy(in_header, w),
+            Opcode::Forget => self.forget(in_header, r),
+            Opcode::BatchForget => self.batch_forget(in_header, r),
+            Opcode::Interrupt => self.interrupt(in_header, r),
+            Opcode::Lookup => self.lookup(in_header, r, w),
+            Opcode::Getattr => self.getattr(in_header, r, w),
+            Opcode::Setattr => self.setattr(in_header, r, w),
+            Opcode::Readlink => self.readlink(in_header, r, w),
+            Opcode::Getxattr => self.getxattr(in_header, r, w),
+            Opcode::Symlink => self.symlink(in_header, r, w),
+            Opcode::Link => self.link(in_header, r, w),
+            Opcode::Create => self.create(in_header, r, w),
+            Opcode::Unlink => self.unlink(in_header, r, w),
+            Opcode::Release => self.release(in_header, r, w),
+            Opcode::Flush => self.flush(in_header, r, w),
+            Opcode::Fsync => self.fsync(in_header, r, w),
+            Opcode::Open => self.open(in_header, r, w),
+            Opcode::Read => self.read(in_header, r, w),
+            Opcode::Write => self.write(in_header, r, w),
+            Opcode::Statfs => self.statfs(in_header, r, w),
+            Opcode::CopyFileRange => self.copy_file_range(in_header, r, w),
+            Opcode::Lseek => self.lseek(in_header, r, w),
+            Opcode::Fallocate => self.fallocate(in_header, r, w),
+            Opcode::Poll => self.poll(in_header, r, w),
+            Opcode::Ioctl => self.ioctl(in_header, r, w),
+            Opcode::Access => self.access(in_header, r, w),
+            Opcode::Mkdir => self.mkdir(in_header, r, w),
+            Opcode::Rmdir => self.rmdir(in_header, r, w),
+            Opcode::Rename => self.rename(in_header, r, w),
+            Opcode::Rename2 => self.rename2(in_header, r, w),
+            Opcode::Releasedir => self.releasedir(in_header, r, w),
+            Opcode::Fsyncdir => self.fsyncdir(in_header, r, w),
+            Opcode::Opendir => self.opendir(in_header, r, w),
+            Opcode::Readdir => self.readdir(in_header, r, w),
+            Opcode::Readdirplus => self.readdirplus(in_header, r, w),
+            Opcode::SetupMapping => self.setup_mapping(in_header, r, w),
+            Opcode::RemoveMapping => self.remove_mapping(in_header, r, w),
         }
     }
+
+    /// Replies EIO to a request whose handling failed, given its header can still be read.
+    pub fn reply_failed(mut r: Reader, w: Writer) -> Result<usize> {
+        let in_header: InHeader = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+        Filesystem::reply_error(in_header.unique, w, libc::EIO)
+    }
+
+    pub fn close_writers(&self) -> Result<()> {
+        self.rt.block_on(self.do_close_writers()).map(|_| ())
+    }
 }
 
 impl Filesystem {
     fn init(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
-        let InitIn { major, minor, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
-
-        if major != KERNEL_VERSION || minor < MIN_KERNEL_MINOR_VERSION {
-            return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+        let InitIn {
+            major,
+            minor,
+            flags,
+            ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!("init: major={} minor={} flags={:#x}", major, minor, flags);
+
+        // a guest with a newer major version retries init with the one ovfs replies with.
+        if major > KERNEL_VERSION {
+            let out = InitOut {
+                major: KERNEL_VERSION,
+                minor: KERNEL_MINOR_VERSION,
+                ..Default::default()
+            };
+            return Filesystem::reply_ok(Some(out), None, in_header.unique, w);
+        }
+        if major < KERNEL_VERSION || minor < MIN_KERNEL_MINOR_VERSION {
+            return Filesystem::reply_error(in_header.unique, w, libc::EPROTO);
         }
+        // both sides speak the older of the two minor versions.
+        let minor = min(minor, KERNEL_MINOR_VERSION);
+        debug!("init: negotiated minor={}", minor);
 
-        let mut attr = OpenedFile::new(FileType::Dir, "/");
-        attr.metadata.ino = DEFAULT_ROOT_DIR_INODE;
-        self.opened_files
-            .insert(attr.clone())
-            .expect("failed to allocate inode");
-        self.opened_files
-            .insert(attr.clone())
-            .expect("failed to allocate inode");
         let mut opened_files_map = self.opened_files_map.lock().unwrap();
-        opened_files_map.insert("/".to_string(), DEFAULT_ROOT_DIR_INODE);
+        if !opened_files_map.contains_key("/") {
+            // slab keys carry the id of the allocating thread, so the root lands wherever the
+            // serving thread's shard puts it and is found through its key from then on.
+            let mut attr = OpenedFile::new(FileType::Dir, "/");
+            attr.metadata.ino = DEFAULT_ROOT_DIR_INODE;
+            let Some(key) = self.opened_files.insert(RwLock::new(attr)) else {
+                warn!("init: failed to allocate the root inode");
+                return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+            };
+            self.root_slab_key.store(key, Ordering::Relaxed);
+            opened_files_map.insert("/".to_string(), DEFAULT_ROOT_DIR_INODE);
+        }
+
+        let capability = self.core().info().full_capability();
+        debug!(
+            "init: backend read={} write_can_multi={} write_can_append={}",
+            capability.read, capability.write_can_multi, capability.write_can_append
+        );
+
+        // the guest only asks for a map alignment when it has a DAX window, otherwise reads
+        // keep being copied through the virtqueue. mappings are filled by reading the range.
+        let mut out_flags = 0;
+        if minor >= MAX_PAGES_MINOR_VERSION {
+            out_flags |= flags & FUSE_MAX_PAGES;
+        }
+        let mut map_alignment = 0;
+        if self.dax && minor >= MAP_ALIGNMENT_MINOR_VERSION && flags & FUSE_MAP_ALIGNMENT != 0 {
+            if capability.read {
+                out_flags |= FUSE_MAP_ALIGNMENT;
+                map_alignment = PAGE_SIZE.trailing_zeros() as u16;
+            } else {
+                warn!("backend can't read, DAX is disabled");
+            }
+        }
+        // cached writes reach the backend out of the guest's write order, which only a
+        // backend taking a write in several parts keeps streaming. the guest also opens every
+        // file it writes for reading too, to fill partially written pages.
+        if self.writeback && flags & FUSE_WRITEBACK_CACHE != 0 {
+            if capability.write_can_multi && capability.read {
+                out_flags |= FUSE_WRITEBACK_CACHE | (flags & FUSE_BIG_WRITES);
+                debug!("init: writeback cache enabled");
+            } else {
+                warn!("backend can't write in several parts or read, writeback cache is disabled");
+            }
+        }
+        if self.compression.is_some() && !capability.write_with_user_metadata {
+            warn!("backend can't store user metadata, files are stored uncompressed");
+        }
+        if self.content_types.is_some() && !capability.write_with_content_type {
+            warn!("backend can't store content types, objects are written without one");
+        }
+        // the guest then leaves clearing setuid and setgid on writes and truncates to ovfs,
+        // sparing a setattr before each of them.
+        if minor >= KILLPRIV_V2_MINOR_VERSION && flags & FUSE_HANDLE_KILLPRIV_V2 != 0 {
+            out_flags |= FUSE_HANDLE_KILLPRIV_V2;
+        }
+        if self.readdirplus && flags & FUSE_DO_READDIRPLUS != 0 {
+            out_flags |= FUSE_DO_READDIRPLUS;
+            debug!("init: readdirplus enabled");
+        }
 
         let out = InitOut {
             major: KERNEL_VERSION,
-            minor: KERNEL_MINOR_VERSION,
-            max_write: MAX_BUFFER_SIZE,
+            minor,
+            flags: out_flags,
+            max_write: self.max_buffer_size,
+            // the kernel caps requests at max_pages, which is only honoured with FUSE_MAX_PAGES.
+            max_pages: (self.max_buffer_size / PAGE_SIZE) as u16,
+            map_alignment,
             ..Default::default()
         };
         Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
 
-    fn destory(&self) -> Result<usize> {
-        // do nothing for destroy.
+    fn destroy(&self, in_header: InHeader, w: Writer) -> Result<usize> {
+        debug!("destroy");
+
+        // a lazy unmount may leave files open, their writers would otherwise never finish
+        // the objects.
+        let closed = self.rt.block_on(self.do_close_writers());
+        let released = self.release_inodes();
+        match closed {
+            Ok(writers) => info!(
+                "destroy: closed {} writers, released {} inodes",
+                writers, released
+            ),
+            Err(err) => warn!(
+                "destroy: failed to close every writer, released {} inodes: {}",
+                released, err
+            ),
+        }
+
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
+    }
+
+    fn forget(&self, in_header: InHeader, mut r: Reader) -> Result<usize> {
+        let ForgetIn { nlookup } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!("forget: inode={} nlookup={}", in_header.nodeid, nlookup);
+
+        self.forget_inode(in_header.nodeid, nlookup);
+        // forget has no reply.
+        Ok(0)
+    }
+
+    fn interrupt(&self, in_header: InHeader, mut r: Reader) -> Result<usize> {
+        let InterruptIn { unique } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!(
+            "interrupt: unique={} interrupted unique={}",
+            in_header.unique, unique
+        );
+
+        // requests of a queue are handled one after another, so the interrupted request has
+        // already been answered and there is nothing left to cancel. interrupt has no reply.
         Ok(0)
     }
 
-    fn forget(&self, _in_header: InHeader) -> Result<usize> {
-        // do nothing for forget.
+    fn batch_forget(&self, _in_header: InHeader, mut r: Reader) -> Result<usize> {
+        let BatchForgetIn { count, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!("batch_forget: count={}", count);
+
+        for _ in 0..count {
+            let ForgetOne { nodeid, nlookup } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+            self.forget_inode(nodeid, nlookup);
+        }
+        // batch forget has no reply.
         Ok(0)
     }
 
     fn lookup(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
-        let name_len = in_header.len as usize - size_of::<InHeader>();
+        let name_len = match Filesystem::payload_len(&in_header, 0) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
         let mut buf = vec![0; name_len];
         r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
+        // . and .. name the directory itself and its parent, they are only looked up explicitly.
         let name = match Filesystem::bytes_to_str(buf.as_ref()) {
-            Ok(name) => name,
+            Ok(name) if Filesystem::is_name_too_long(name) => {
+                return Filesystem::reply_error(in_header.unique, w, libc::ENAMETOOLONG)
+            }
+            Ok(name) if Filesystem::is_entry_name(name) || name == "." || name == ".." => name,
+            Ok(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
             Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
         };
 
         debug!("lookup: parent inode={} name={}", in_header.nodeid, name);
 
         let parent_path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
         {
             Some(path) => path,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        let path = format!("{}/{}", parent_path, name);
-        let metadata = match self.rt.block_on(self.do_get_metadata(&path)) {
+        if self
+            .negative_lookups
+            .lock()
+            .unwrap()
+            .contains(in_header.nodeid, name)
+        {
+            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
+        }
+
+        let path = match name {
+            "." => parent_path.clone(),
+            ".." => Filesystem::parent_path(&parent_path).to_string(),
+            _ => Filesystem::join_path(&parent_path, name),
+        };
+        let mut result = self.rt.block_on(self.do_get_metadata(&path, true));
+        if self.case_insensitive
+            && result
+                .as_ref()
+                .is_err_and(|err| Filesystem::backend_errno(err, libc::ENOENT) == libc::ENOENT)
+        {
+            result = self
+                .rt
+                .block_on(self.do_lookup_ignore_case(&parent_path, name));
+        }
+        let metadata = match result {
             Ok(metadata) => metadata,
-            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+            Err(err) => {
+                let errno = Filesystem::backend_errno(&err, libc::ENOENT);
+                // a backend that didn't answer in time says nothing about the entry.
+                if errno == libc::ENOENT {
+                    self.negative_lookups
+                        .lock()
+                        .unwrap()
+                        .insert(in_header.nodeid, name);
+                }
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
         };
 
         let out = EntryOut {
             nodeid: metadata.metadata.ino,
-            entry_valid: DEFAULT_TTL.as_secs(),
-            attr_valid: DEFAULT_TTL.as_secs(),
-            entry_valid_nsec: DEFAULT_TTL.subsec_nanos(),
-            attr_valid_nsec: DEFAULT_TTL.subsec_nanos(),
-            attr: metadata.metadata,
-            ..Default::default()
+            generation: metadata.generation,
+            entry_valid: self.entry_timeout.as_secs(),
+            attr_valid: self.attr_timeout.as_secs(),
+            entry_valid_nsec: self.entry_timeout.subsec_nanos(),
+            attr_valid_nsec: self.attr_timeout.subsec_nanos(),
+            attr: self.owned_attr(&in_header, &metadata),
         };
         Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
@@ -227,43 +833,176 @@ impl Filesystem {
         debug!("getattr: inode={}", in_header.nodeid);
 
         let path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
         {
             Some(path) => path,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        let metadata = match self.rt.block_on(self.do_get_metadata(&path)) {
+        let metadata = match self.get_inode_metadata(in_header.nodeid, &path) {
             Ok(metadata) => metadata,
-            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+            Err(err) => {
+                let errno = Filesystem::backend_errno(&err, libc::ENOENT);
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
         };
 
         let out = AttrOut {
-            attr_valid: DEFAULT_TTL.as_secs(),
-            attr_valid_nsec: DEFAULT_TTL.subsec_nanos(),
-            attr: metadata.metadata,
+            attr_valid: self.attr_timeout.as_secs(),
+            attr_valid_nsec: self.attr_timeout.subsec_nanos(),
+            attr: self.owned_attr(&in_header, &metadata),
             ..Default::default()
         };
         Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
 
-    fn setattr(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
-        debug!("setattr: inode={}", in_header.nodeid);
+    fn setattr(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let SetattrIn {
+            valid,
+            fh,
+            size,
+            mode,
+            uid,
+            gid,
+            ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!(
+            "setattr: inode={} valid={} fh={} size={} mode={:o} uid={} gid={}",
+            in_header.nodeid, valid, fh, size, mode, uid, gid
+        );
+
+        // an ftruncate names the handle it goes through, which must be one of the inode's.
+        let fh = if valid & FATTR_FH != 0 {
+            if self.handle_inode(fh) != Some(in_header.nodeid) {
+                return Filesystem::reply_error(in_header.unique, w, libc::EBADF);
+            }
+            Some(fh)
+        } else {
+            None
+        };
+
+        let path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let mut metadata = match self.get_inode_metadata(in_header.nodeid, &path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        // the fs service keeps the mode on disk, where other tools see it as well.
+        if valid & FATTR_MODE != 0 && self.with_orphan(in_header.nodeid, |_| ()).is_none() {
+            if let Err(err) = self.set_local_mode(&path, mode) {
+                let errno = err.raw_os_error().unwrap_or(libc::EIO);
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
+        }
+
+        // times are accepted but not persisted.
+        if valid & (FATTR_MODE | FATTR_UID | FATTR_GID) != 0 {
+            if valid & FATTR_MODE != 0 {
+                metadata.set_permissions(mode);
+            }
+            if valid & (FATTR_UID | FATTR_GID) != 0 {
+                let shown = self.owned_attr(&in_header, &metadata);
+                metadata.owner = Some((
+                    if valid & FATTR_UID != 0 {
+                        uid
+                    } else {
+                        shown.uid
+                    },
+                    if valid & FATTR_GID != 0 {
+                        gid
+                    } else {
+                        shown.gid
+                    },
+                ));
+            }
+            if let Some(file) = self.opened_file(in_header.nodeid) {
+                let mut file = file.write().unwrap();
+                file.set_permissions(metadata.metadata.mode);
+                file.owner = metadata.owner;
+            }
+            // directories have no object of their own to carry user metadata, their mode and
+            // owner only live as long as the cached inode.
+            if metadata.metadata.mode & libc::S_IFMT == libc::S_IFREG
+                && self
+                    .core()
+                    .info()
+                    .full_capability()
+                    .write_with_user_metadata
+                && self.rt.block_on(self.do_persist_metadata(&path)).is_err()
+            {
+                return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+            }
+        }
+
+        // backends keeping user metadata store the cleared mode with the next whole write.
+        if valid & FATTR_KILL_SUIDGID != 0 {
+            metadata.kill_suidgid();
+            if let Some(file) = self.opened_file(in_header.nodeid) {
+                file.write().unwrap().kill_suidgid();
+            }
+            self.store_local_mode(&path, metadata.metadata.mode);
+        }
+
+        if valid & FATTR_SIZE != 0 {
+            if metadata.metadata.mode & libc::S_IFMT == libc::S_IFDIR {
+                return Filesystem::reply_error(in_header.unique, w, libc::EISDIR);
+            }
+            if !self.core().info().full_capability().write {
+                return Filesystem::reply_error(in_header.unique, w, libc::EINVAL);
+            }
+            // unlinked files live in memory, so they are bounded like a rewrite.
+            let result = self
+                .with_orphan(in_header.nodeid, |data| {
+                    self.check_file_size(size)?;
+                    data.resize(size as usize, 0);
+                    Ok(())
+                })
+                .unwrap_or_else(|| self.rt.block_on(self.do_truncate(fh, &path, size)));
+            if let Err(err) = result {
+                let errno = Filesystem::backend_errno(&err, libc::EIO);
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
+            metadata.set_size(size);
+            if let Some(file) = self.opened_file(in_header.nodeid) {
+                file.write().unwrap().set_size(size);
+            }
+        }
 
-        // do nothing for setattr.
-        self.getattr(in_header, _r, w)
+        let out = AttrOut {
+            attr_valid: self.attr_timeout.as_secs(),
+            attr_valid_nsec: self.attr_timeout.subsec_nanos(),
+            attr: self.owned_attr(&in_header, &metadata),
+            ..Default::default()
+        };
+        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
 
     fn create(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
-        let CreateIn { flags, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+        let CreateIn {
+            flags, mode, umask, ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        let name_len = in_header.len as usize - size_of::<InHeader>() - size_of::<CreateIn>();
+        let name_len = match Filesystem::payload_len(&in_header, size_of::<CreateIn>()) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
         let mut buf = vec![0; name_len];
         r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
         let name = match Filesystem::bytes_to_str(buf.as_ref()) {
-            Ok(name) => name,
+            Ok(name) if Filesystem::is_name_too_long(name) => {
+                return Filesystem::reply_error(in_header.unique, w, libc::ENAMETOOLONG)
+            }
+            Ok(name) if Filesystem::is_entry_name(name) => name,
+            Ok(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
             Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
         };
 
@@ -273,39 +1012,59 @@ impl Filesystem {
         );
 
         let parent_path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
         {
             Some(path) => path,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        let path = format!("{}/{}", parent_path, name);
+        self.invalidate_negative_lookup(in_header.nodeid, name);
+        self.invalidate_dir_cache(&parent_path);
+        let path = Filesystem::join_path(&parent_path, name);
         let mut attr = OpenedFile::new(FileType::File, &path);
-        let inode = self
-            .opened_files
-            .insert(attr.clone())
-            .expect("failed to allocate inode");
-        attr.metadata.ino = inode as u64;
+        attr.set_permissions(mode & !umask);
+        // the object only exists once its writer closes, so stamp the entry with the creation time.
+        attr.set_times(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        );
+        attr.nlookup = 1;
         let mut opened_files_map = self.opened_files_map.lock().unwrap();
-        opened_files_map.insert(path.to_string(), inode as u64);
-
-        match self.rt.block_on(self.do_set_writer(&path, flags)) {
-            Ok(writer) => writer,
-            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
-        };
+        let inode = self.insert_inode(&mut opened_files_map, &mut attr);
+
+        // a freshly created object has nothing to truncate.
+        let flags = flags & !(libc::O_TRUNC as u32);
+        let fh = self.insert_handle(inode, flags);
+        match self.rt.block_on(self.do_set_writer(
+            fh,
+            &path,
+            flags,
+            Filesystem::object_metadata(&attr),
+        )) {
+            // the file only exists on disk once a writer opened it.
+            Ok(true) => self.store_local_mode(&path, attr.metadata.mode),
+            Ok(false) => {}
+            Err(err) => {
+                self.remove_handle(fh);
+                let errno = Filesystem::backend_errno(&err, libc::ENOENT);
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
+        }
 
         let entry_out = EntryOut {
             nodeid: attr.metadata.ino,
-            entry_valid: DEFAULT_TTL.as_secs(),
-            attr_valid: DEFAULT_TTL.as_secs(),
-            entry_valid_nsec: DEFAULT_TTL.subsec_nanos(),
-            attr_valid_nsec: DEFAULT_TTL.subsec_nanos(),
-            attr: attr.metadata,
-            ..Default::default()
+            generation: attr.generation,
+            entry_valid: self.entry_timeout.as_secs(),
+            attr_valid: self.attr_timeout.as_secs(),
+            entry_valid_nsec: self.entry_timeout.subsec_nanos(),
+            attr_valid_nsec: self.attr_timeout.subsec_nanos(),
+            attr: self.owned_attr(&in_header, &attr),
         };
         let open_out = OpenOut {
+            fh,
+            open_flags: Filesystem::open_flags(flags),
             ..Default::default()
         };
         Filesystem::reply_ok(
@@ -317,29 +1076,66 @@ impl Filesystem {
     }
 
     fn unlink(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
-        let name_len = in_header.len as usize - size_of::<InHeader>();
+        let name_len = match Filesystem::payload_len(&in_header, 0) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
         let mut buf = vec![0; name_len];
         r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
         let name = match Filesystem::bytes_to_str(buf.as_ref()) {
-            Ok(name) => name,
+            Ok(name) if Filesystem::is_name_too_long(name) => {
+                return Filesystem::reply_error(in_header.unique, w, libc::ENAMETOOLONG)
+            }
+            Ok(name) if Filesystem::is_entry_name(name) => name,
+            Ok(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
             Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
         };
 
         debug!("unlink: parent inode={} name={}", in_header.nodeid, name);
 
         let parent_path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
         {
             Some(path) => path,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        let path = format!("{}/{}", parent_path, name);
+        let path = self.rt.block_on(self.do_resolve_name(&parent_path, name));
+
+        // a file still open is kept readable and writable through its handles, so its contents
+        // are copied out before the object goes away.
+        let open_inode = self
+            .opened_files_map
+            .lock()
+            .unwrap()
+            .get(&path)
+            .copied()
+            .filter(|inode| {
+                self.opened_file(*inode)
+                    .is_some_and(|f| f.read().unwrap().opens > 0)
+            });
+        if let Some(inode) = open_inode {
+            let data = match self.rt.block_on(self.do_orphan_contents(&path)) {
+                Ok(data) => data,
+                Err(err) => {
+                    let errno = Filesystem::backend_errno(&err, libc::EIO);
+                    return Filesystem::reply_error(in_header.unique, w, errno);
+                }
+            };
+            self.orphans.lock().unwrap().insert(inode, data);
+            if let Some(file) = self.opened_file(inode) {
+                file.write().unwrap().metadata.nlink = 0;
+            }
+        }
+
         if self.rt.block_on(self.do_delete(&path)).is_err() {
+            if let Some(inode) = open_inode {
+                self.orphans.lock().unwrap().remove(&inode);
+            }
             return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
         }
+        self.invalidate_dir_cache(&parent_path);
 
         let mut opened_files_map = self.opened_files_map.lock().unwrap();
         opened_files_map.remove(&path);
@@ -347,75 +1143,162 @@ impl Filesystem {
         Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
     }
 
-    fn release(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
-        debug!("release: inode={}", in_header.nodeid);
+    fn release(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let ReleaseIn { fh, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        let path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
-        {
-            Some(path) => path,
-            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
-        };
+        debug!("release: inode={} fh={}", in_header.nodeid, fh);
 
-        if self.rt.block_on(self.do_release_writer(&path)).is_err() {
-            return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+        if !self.remove_handle(fh) {
+            return Filesystem::reply_error(in_header.unique, w, libc::EBADF);
+        }
+
+        if let Err(err) = self.rt.block_on(self.do_release_writer(fh)) {
+            let errno = Filesystem::backend_errno(&err, libc::EIO);
+            return Filesystem::reply_error(in_header.unique, w, errno);
         }
 
         Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
     }
 
-    fn flush(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
-        debug!("flush: inode={}", in_header.nodeid);
+    fn flush(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let FlushIn { fh, lock_owner, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        // ovfs holds no POSIX locks, so there are none of the owner's to release.
+        debug!(
+            "flush: inode={} fh={} lock_owner={}",
+            in_header.nodeid, fh, lock_owner
+        );
 
-        if self.opened_files.get(in_header.nodeid as usize).is_none() {
+        if self.opened_file(in_header.nodeid).is_none() {
             return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
         }
 
+        // buffered writes are only handed to the backend writer. the fs backend persists
+        // them right away, object stores commit nothing until the writer is closed by fsync
+        // or release, so a flush there is not durable.
+        if let Err(err) = self.rt.block_on(self.do_flush_writer(fh)) {
+            let errno = Filesystem::backend_errno(&err, libc::EIO);
+            return Filesystem::reply_error(in_header.unique, w, errno);
+        }
+
         Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
     }
 
-    fn open(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
-        debug!("open: inode={}", in_header.nodeid);
+    fn fsync(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let FsyncIn {
+            fh, fsync_flags, ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!(
+            "fsync: inode={} fh={} fsync_flags={}",
+            in_header.nodeid, fh, fsync_flags
+        );
+
+        if self.opened_file(in_header.nodeid).is_none() {
+            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
+        }
+
+        // closing the streaming writer commits its data, the handle streams on afterwards.
+        if let Err(err) = self.rt.block_on(self.do_sync_writer(fh)) {
+            let errno = Filesystem::backend_errno(&err, libc::EIO);
+            return Filesystem::reply_error(in_header.unique, w, errno);
+        }
+
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
+    }
 
+    fn open(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
         let OpenIn { flags, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        let path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
-        {
-            Some(path) => path,
+        debug!(
+            "open: inode={} flags={:#x} direct_io={}",
+            in_header.nodeid,
+            flags,
+            Filesystem::is_direct_io(flags)
+        );
+
+        if self.read_only && Filesystem::is_write_flags(flags) {
+            return Filesystem::reply_error(in_header.unique, w, libc::EROFS);
+        }
+        // fail the open rather than a later read or write the backend can't serve.
+        if let Err(err) = self.check_flags(flags) {
+            let errno = err.errno().unwrap_or(libc::EACCES);
+            return Filesystem::reply_error(in_header.unique, w, errno);
+        }
+
+        let (path, metadata) = match self.opened_file(in_header.nodeid).map(|f| {
+            let file = f.read().unwrap();
+            (file.path.clone(), Filesystem::object_metadata(&file))
+        }) {
+            Some(file) => file,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        match self.rt.block_on(self.do_set_writer(&path, flags)) {
-            Ok(writer) => writer,
-            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        let fh = self.insert_handle(in_header.nodeid, flags);
+        let truncated = match self
+            .rt
+            .block_on(self.do_set_writer(fh, &path, flags, metadata))
+        {
+            Ok(truncated) => truncated,
+            Err(err) => {
+                self.remove_handle(fh);
+                let errno = Filesystem::backend_errno(&err, libc::ENOENT);
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
         };
+        if truncated {
+            if let Some(file) = self.opened_file(in_header.nodeid) {
+                file.write().unwrap().set_size(0);
+            }
+        }
 
         let out = OpenOut {
+            fh,
+            open_flags: Filesystem::open_flags(flags),
             ..Default::default()
         };
         Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
 
     fn read(&self, in_header: InHeader, mut r: Reader, mut w: Writer) -> Result<usize> {
-        let ReadIn { offset, size, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+        let ReadIn {
+            fh, offset, size, ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        let path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
-        {
-            Some(path) => path,
+        let inode = match self.handle_inode(fh) {
+            Some(inode) => inode,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EBADF),
+        };
+        let (path, file_size) = match self.opened_file(inode).map(|f| {
+            let file = f.read().unwrap();
+            (file.path.clone(), file.metadata.size)
+        }) {
+            Some(file) => file,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        let data = match self.rt.block_on(self.do_read(&path, offset)) {
+        // reading at or past the end is an empty read, not a backend error.
+        if offset >= file_size {
+            return Filesystem::reply_ok(None::<u8>, None, in_header.unique, w);
+        }
+
+        let orphan = self.with_orphan(inode, |data| {
+            let start = min(offset as usize, data.len());
+            let end = min(start + size as usize, data.len());
+            Buffer::from(data[start..end].to_vec())
+        });
+        let read = match orphan {
+            Some(data) => Ok(data),
+            None => self
+                .rt
+                .block_on(self.do_read(&path, offset, size, self.handle_direct_io(fh))),
+        };
+        let data = match read {
             Ok(data) => data,
-            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+            Err(err) => {
+                let errno = Filesystem::backend_errno(&err, libc::ENOENT);
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
         };
         let len = data.len();
         let buffer = BufferWrapper::new(data);
@@ -441,17 +1324,31 @@ impl Filesystem {
     }
 
     fn write(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
-        let WriteIn { offset, size, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+        let WriteIn {
+            fh,
+            offset,
+            size,
+            write_flags,
+            ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
         debug!(
-            "write: inode={} offset={} size={}",
-            in_header.nodeid, offset, size
+            "write: inode={} fh={} offset={} size={}",
+            in_header.nodeid, fh, offset, size
         );
 
+        let inode = match self.opened_handles.get(fh as usize).map(|h| {
+            let handle = h.read().unwrap();
+            (handle.inode, handle.flags)
+        }) {
+            Some((inode, flags)) if flags & libc::O_ACCMODE as u32 != libc::O_RDONLY as u32 => {
+                inode
+            }
+            _ => return Filesystem::reply_error(in_header.unique, w, libc::EBADF),
+        };
         let path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
+            .opened_file(inode)
+            .map(|f| f.read().unwrap().path.clone())
         {
             Some(path) => path,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
@@ -462,10 +1359,40 @@ impl Filesystem {
             .map_err(|_| Error::from(libc::EIO))?;
         let buffer = buffer.get_buffer();
 
-        match self.rt.block_on(self.do_write(&path, offset, buffer)) {
-            Ok(writer) => writer,
-            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
-        };
+        let result = self
+            .with_orphan(inode, |data| {
+                let end = offset
+                    .checked_add(buffer.len() as u64)
+                    .ok_or(Error::from(libc::EFBIG))?;
+                self.check_file_size(end)?;
+                let start = offset as usize;
+                let end = end as usize;
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[start..end].copy_from_slice(&buffer.to_vec());
+                Ok(())
+            })
+            .unwrap_or_else(|| {
+                self.rt
+                    .block_on(self.do_write(fh, &path, offset, buffer))
+                    .map(|_| ())
+            });
+        if let Err(err) = result {
+            let errno = Filesystem::backend_errno(&err, libc::EIO);
+            return Filesystem::reply_error(in_header.unique, w, errno);
+        }
+        self.extend_cached_size(inode, offset + size as u64);
+        if write_flags & FUSE_WRITE_KILL_SUIDGID != 0 {
+            let mode = self.opened_file(inode).map(|file| {
+                let mut file = file.write().unwrap();
+                file.kill_suidgid();
+                file.metadata.mode
+            });
+            if let Some(mode) = mode {
+                self.store_local_mode(&path, mode);
+            }
+        }
 
         let out = WriteOut {
             size,
@@ -474,378 +1401,2599 @@ impl Filesystem {
         Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
 
-    fn mkdir(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
-        let MkdirIn { .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+    fn copy_file_range(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let CopyFileRangeIn {
+            off_in,
+            nodeid_out,
+            fh_out,
+            off_out,
+            len,
+            ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        let name_len = in_header.len as usize - size_of::<InHeader>() - size_of::<MkdirIn>();
-        let mut buf = vec![0; name_len];
-        r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
-        let name = match Filesystem::bytes_to_str(buf.as_ref()) {
-            Ok(name) => name,
-            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
-        };
-
-        debug!("mkdir: parent inode={} name={}", in_header.nodeid, name);
+        debug!(
+            "copy_file_range: inode={} offset={} out inode={} out offset={} len={}",
+            in_header.nodeid, off_in, nodeid_out, off_out, len
+        );
 
-        let parent_path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
+        let src_path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+        let dst_path = match self
+            .opened_file(nodeid_out)
+            .map(|f| f.read().unwrap().path.clone())
         {
             Some(path) => path,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        let path = format!("{}/{}", parent_path, name);
-        let mut attr = OpenedFile::new(FileType::Dir, &path);
-        let inode = self
-            .opened_files
-            .insert(attr.clone())
-            .expect("failed to allocate inode");
-        attr.metadata.ino = inode as u64;
-        let mut opened_files_map = self.opened_files_map.lock().unwrap();
-        opened_files_map.insert(path.to_string(), inode as u64);
-
-        if self.rt.block_on(self.do_create_dir(&path)).is_err() {
-            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
-        }
+        let copied = match self
+            .rt
+            .block_on(self.do_copy_file_range(&src_path, off_in, fh_out, &dst_path, off_out, len))
+        {
+            Ok(copied) => copied,
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
+        };
+        self.extend_cached_size(nodeid_out, off_out + copied);
 
-        let out = EntryOut {
-            nodeid: attr.metadata.ino,
-            entry_valid: DEFAULT_TTL.as_secs(),
-            attr_valid: DEFAULT_TTL.as_secs(),
-            entry_valid_nsec: DEFAULT_TTL.subsec_nanos(),
-            attr_valid_nsec: DEFAULT_TTL.subsec_nanos(),
-            attr: attr.metadata,
+        let out = WriteOut {
+            size: copied as u32,
             ..Default::default()
         };
         Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
 
-    fn rmdir(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
-        let name_len = in_header.len as usize - size_of::<InHeader>();
-        let mut buf = vec![0; name_len];
-        r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
-        let name = match Filesystem::bytes_to_str(buf.as_ref()) {
-            Ok(name) => name,
-            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
-        };
+    fn access(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let AccessIn { mask, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        debug!("rmdir: parent inode={} name={}", in_header.nodeid, name);
+        debug!("access: inode={} mask={}", in_header.nodeid, mask);
 
-        let parent_path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
+        let mode = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().metadata.mode)
         {
-            Some(path) => path,
+            Some(mode) => mode,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        let path = format!("{}/{}", parent_path, name);
-        if self.rt.block_on(self.do_delete(&path)).is_err() {
-            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
+        if self.read_only && mask & libc::W_OK as u32 != 0 {
+            return Filesystem::reply_error(in_header.unique, w, libc::EROFS);
         }
 
-        let mut opened_files_map = self.opened_files_map.lock().unwrap();
-        opened_files_map.remove(&path);
+        let capability = self.core().info().full_capability();
+        let checks = [
+            (libc::R_OK, capability.read),
+            (libc::W_OK, capability.write),
+            (libc::X_OK, true),
+        ];
+        for (bit, supported) in checks {
+            if mask & bit as u32 == 0 {
+                continue;
+            }
+            // the bit may be granted to any of owner, group or others.
+            let granted = mode & (bit as u32 * 0o111) != 0;
+            if !supported || !granted {
+                return Filesystem::reply_error(in_header.unique, w, libc::EACCES);
+            }
+        }
 
         Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
     }
 
-    fn releasedir(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
-        debug!("releasedir: inode={}", in_header.nodeid);
+    fn poll(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let PollIn { events, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!("poll: inode={} events={}", in_header.nodeid, events);
 
-        if self.opened_files.get(in_header.nodeid as usize).is_none() {
+        if self.opened_file(in_header.nodeid).is_none() {
             return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
         }
 
-        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
+        // objects never block, so they are always ready and no wakeup is registered.
+        let out = PollOut {
+            revents: (libc::POLLIN | libc::POLLOUT) as u32,
+            ..Default::default()
+        };
+        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
 
-    fn fsyncdir(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
-        debug!("fsyncdir: inode={}", in_header.nodeid);
+    fn ioctl(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let IoctlIn {
+            flags,
+            cmd,
+            arg,
+            out_size,
+            ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        if self.opened_files.get(in_header.nodeid as usize).is_none() {
-            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
-        }
+        debug!(
+            "ioctl: inode={} cmd={:#x} flags={} out_size={}",
+            in_header.nodeid, cmd, flags, out_size
+        );
 
-        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
+        match cmd {
+            OVFS_IOC_STATS => {
+                let mut stats = self.stats().to_json().into_bytes();
+                stats.push(0);
+                if stats.len() > out_size as usize {
+                    // an unrestricted caller is asked to retry with a buffer large enough,
+                    // the size of any other one is fixed by the command.
+                    if flags & FUSE_IOCTL_UNRESTRICTED == 0 {
+                        return Filesystem::reply_error(in_header.unique, w, libc::ERANGE);
+                    }
+                    let out = IoctlOut {
+                        flags: FUSE_IOCTL_RETRY,
+                        out_iovs: 1,
+                        ..Default::default()
+                    };
+                    let iovec = IoctlIovec {
+                        base: arg,
+                        len: stats.len() as u64,
+                    };
+                    return Filesystem::reply_ok(
+                        Some(out),
+                        Some(iovec.as_slice()),
+                        in_header.unique,
+                        w,
+                    );
+                }
+                Filesystem::reply_ok(Some(IoctlOut::default()), Some(&stats), in_header.unique, w)
+            }
+            OVFS_IOC_FLUSH_ALL => match self.rt.block_on(self.do_close_writers()) {
+                Ok(writers) => {
+                    let out = IoctlOut {
+                        result: writers as i32,
+                        ..Default::default()
+                    };
+                    Filesystem::reply_ok(Some(out), None, in_header.unique, w)
+                }
+                Err(err) => {
+                    let errno = Filesystem::backend_errno(&err, libc::EIO);
+                    Filesystem::reply_error(in_header.unique, w, errno)
+                }
+            },
+            _ => Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        }
     }
 
-    fn opendir(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
-        debug!("opendir: inode={}", in_header.nodeid);
+    fn fallocate(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let FallocateIn {
+            fh,
+            offset,
+            length,
+            mode,
+            ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        if self.opened_files.get(in_header.nodeid as usize).is_none() {
-            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
+        debug!(
+            "fallocate: inode={} offset={} length={} mode={}",
+            in_header.nodeid, offset, length, mode
+        );
+
+        let (path, size) = match self.opened_file(in_header.nodeid).map(|f| {
+            let file = f.read().unwrap();
+            (file.path.clone(), file.metadata.size)
+        }) {
+            Some(file) => file,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        if !self.core().info().full_capability().write {
+            return Filesystem::reply_error(in_header.unique, w, libc::EOPNOTSUPP);
         }
 
-        let out = OpenOut {
-            ..Default::default()
+        let end = match offset.checked_add(length) {
+            Some(end) => end,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EFBIG),
         };
-        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
+        let mode = mode as i32;
+        let keep_size = mode & libc::FALLOC_FL_KEEP_SIZE != 0;
+        let result = match mode & !libc::FALLOC_FL_KEEP_SIZE {
+            // objects are always fully allocated, so only growing the size has an effect.
+            0 if !keep_size && end > size => {
+                let result = self
+                    .check_file_size(end)
+                    .and_then(|_| self.rt.block_on(self.do_truncate(Some(fh), &path, end)));
+                if result.is_ok() {
+                    self.extend_cached_size(in_header.nodeid, end);
+                }
+                result
+            }
+            0 => Ok(()),
+            libc::FALLOC_FL_PUNCH_HOLE if keep_size => self
+                .rt
+                .block_on(self.do_punch_hole(fh, &path, offset, length, size)),
+            _ => return Filesystem::reply_error(in_header.unique, w, libc::EOPNOTSUPP),
+        };
+        if let Err(err) = result {
+            let errno = Filesystem::backend_errno(&err, libc::EIO);
+            return Filesystem::reply_error(in_header.unique, w, errno);
+        }
+
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
     }
 
-    fn readdir(&self, in_header: InHeader, mut r: Reader, mut w: Writer) -> Result<usize> {
+    fn lseek(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let LseekIn { offset, whence, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!(
+            "lseek: inode={} offset={} whence={}",
+            in_header.nodeid, offset, whence
+        );
+
         let path = match self
-            .opened_files
-            .get(in_header.nodeid as usize)
-            .map(|f| f.path.clone())
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
         {
             Some(path) => path,
             None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
 
-        let ReadIn { offset, size, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
-
-        debug!(
-            "readdir: inode={} offset={} size={}",
-            in_header.nodeid, offset, size
-        );
-
-        let mut data_writer = w.split_at(size_of::<OutHeader>()).unwrap();
-
-        let entries = match self.rt.block_on(self.do_readdir(&path)) {
-            Ok(entries) => entries,
+        let metadata = match self.rt.block_on(self.do_get_metadata(&path, false)) {
+            Ok(metadata) => metadata,
             Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
         };
+        let size = metadata.metadata.size;
 
-        if offset as usize >= entries.len() {
-            let out = OutHeader {
-                len: size_of::<OutHeader>() as u32,
-                error: 0,
-                unique: in_header.unique,
-            };
-            w.write_all(out.as_slice())
-                .map_err(|_| Error::from(libc::EIO))?;
-            return Ok(out.len as usize);
-        }
-
-        let mut total_written = 0;
-        for entry in entries {
-            match Filesystem::reply_add_dir_entry(&mut data_writer, entry) {
-                Ok(len) => {
-                    total_written += len;
-                }
-                Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
-            };
+        // objects have no holes, so the whole object is reported as data.
+        if offset >= size {
+            return Filesystem::reply_error(in_header.unique, w, libc::ENXIO);
         }
-
-        let out = OutHeader {
-            len: (size_of::<OutHeader>() + total_written) as u32,
-            error: 0,
-            unique: in_header.unique,
+        let offset = match whence as i32 {
+            libc::SEEK_DATA => offset,
+            libc::SEEK_HOLE => size,
+            _ => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
         };
 
-        w.write_all(out.as_slice())
-            .map_err(|_| Error::from(libc::EIO))?;
-        Ok(out.len as usize)
+        let out = LseekOut { offset };
+        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
-}
 
-impl Filesystem {
-    fn reply_ok<T: ByteValued>(
-        out: Option<T>,
-        data: Option<&[u8]>,
-        unique: u64,
-        mut w: Writer,
-    ) -> Result<usize> {
-        let mut len = size_of::<OutHeader>();
-        if out.is_some() {
-            len += size_of::<T>();
-        }
-        if let Some(data) = data {
-            len += data.len();
-        }
-        let header = OutHeader {
-            unique,
-            error: 0,
-            len: len as u32,
-        };
-        w.write_all(header.as_slice())
-            .map_err(|_| Error::from(libc::EIO))?;
-        if let Some(out) = out {
-            w.write_all(out.as_slice())
-                .map_err(|_| Error::from(libc::EIO))?;
-        }
-        if let Some(data) = data {
-            w.write_all(data).map_err(|_| Error::from(libc::EIO))?;
-        }
-        Ok(w.bytes_written())
-    }
+    fn setup_mapping(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let SetupMappingIn {
+            foffset,
+            len,
+            flags,
+            moffset,
+            ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-    fn reply_add_dir_entry(cursor: &mut Writer, entry: DirEntry) -> Result<usize> {
-        let entry_len = size_of::<DirEntryOut>() + entry.name.len();
-        let total_len = (entry_len + 7) & !7;
+        debug!(
+            "setupmapping: inode={} foffset={} len={} flags={} moffset={}",
+            in_header.nodeid, foffset, len, flags, moffset
+        );
 
-        let out = DirEntryOut {
-            ino: entry.ino,
-            off: entry.off,
-            namelen: entry.name.len() as u32,
-            type_: entry.type_,
+        let backend_req = match self.backend_req.lock().unwrap().clone() {
+            Some(backend_req) if self.dax => backend_req,
+            _ => return Filesystem::reply_error(in_header.unique, w, libc::ENOSYS),
         };
 
-        cursor
-            .write_all(out.as_slice())
-            .map_err(|_| Error::from(libc::EIO))?;
-        cursor
-            .write_all(entry.name.as_bytes())
-            .map_err(|_| Error::from(libc::EIO))?;
-
-        let padding = total_len - entry_len;
-        if padding > 0 {
-            cursor
-                .write_all(&DIRENT_PADDING[..padding])
-                .map_err(|_| Error::from(libc::EIO))?;
+        // the mapped region is a copy of the object, so stores into it would never reach the backend.
+        if flags & FUSE_SETUPMAPPING_FLAG_WRITE != 0 {
+            return Filesystem::reply_error(in_header.unique, w, libc::EOPNOTSUPP);
         }
 
-        Ok(total_len)
-    }
+        let path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
 
-    fn reply_error(unique: u64, mut w: Writer, error: libc::c_int) -> Result<usize> {
-        let header = OutHeader {
-            unique,
-            error: -error,
-            len: size_of::<OutHeader>() as u32,
+        let region = match self.rt.block_on(self.do_setup_mapping(&path, foffset, len)) {
+            Ok(region) => region,
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
         };
-        w.write_all(header.as_slice())
-            .map_err(|_| Error::from(libc::EIO))?;
-        Ok(w.bytes_written())
-    }
 
-    fn bytes_to_str(buf: &[u8]) -> Result<&str> {
-        Filesystem::bytes_to_cstr(buf)?
-            .to_str()
-            .map_err(|_| Error::from(libc::EINVAL))
-    }
+        let mut msg = VhostUserFSBackendMsg::default();
+        msg.len[0] = len;
+        msg.cache_offset[0] = moffset;
+        msg.flags[0] = VhostUserFSBackendMsgFlags::MAP_R;
+        if backend_req.fs_backend_map(&msg, &region).is_err() {
+            return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+        }
 
-    fn bytes_to_cstr(buf: &[u8]) -> Result<&CStr> {
-        CStr::from_bytes_with_nul(buf).map_err(|_| Error::from(libc::EINVAL))
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
     }
 
-    fn check_flags(&self, flags: u32) -> Result<(bool, bool)> {
-        let is_trunc = flags & libc::O_TRUNC as u32 != 0 || flags & libc::O_CREAT as u32 != 0;
-        let is_append = flags & libc::O_APPEND as u32 != 0;
-        let mode = flags & libc::O_ACCMODE as u32;
-        let is_write = mode == libc::O_WRONLY as u32 || mode == libc::O_RDWR as u32 || is_append;
+    fn remove_mapping(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let RemoveMappingIn { count } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        let capability = self.core.info().full_capability();
-        if is_trunc && !capability.write {
-            Err(Error::from(libc::EACCES))?;
-        }
-        if is_append && !capability.write_can_append {
-            Err(Error::from(libc::EACCES))?;
-        }
-        Ok((is_write, is_append))
-    }
-}
+        debug!("removemapping: inode={} count={}", in_header.nodeid, count);
 
-impl Filesystem {
-    async fn do_get_metadata(&self, path: &str) -> Result<OpenedFile> {
-        let metadata = self.core.stat(path).await.map_err(|err| Error::from(err))?;
-        let file_type = match metadata.mode() {
-            opendal::EntryMode::DIR => FileType::Dir,
-            _ => FileType::File,
+        let backend_req = match self.backend_req.lock().unwrap().clone() {
+            Some(backend_req) if self.dax => backend_req,
+            _ => return Filesystem::reply_error(in_header.unique, w, libc::ENOSYS),
         };
-        let mut attr = OpenedFile::new(file_type, path);
-        attr.metadata.size = metadata.content_length();
-        let mut opened_files_map = self.opened_files_map.lock().unwrap();
-        if let Some(inode) = opened_files_map.get(path) {
-            attr.metadata.ino = *inode;
-        } else {
-            let inode = self
-                .opened_files
-                .insert(attr.clone())
-                .expect("failed to allocate inode");
-            attr.metadata.ino = inode as u64;
-            opened_files_map.insert(path.to_string(), inode as u64);
-        }
-
-        Ok(attr)
-    }
 
-    async fn do_set_writer(&self, path: &str, flags: u32) -> Result<()> {
-        let (is_write, is_append) = self.check_flags(flags)?;
-        if !is_write {
-            return Ok(());
+        let mut mappings = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mapping: RemoveMappingOne = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+            mappings.push(mapping);
         }
 
-        let writer = self
-            .core
-            .writer_with(path)
-            .append(is_append)
-            .await
-            .map_err(|err| Error::from(err))?;
-        let written = if is_append {
-            self.core
-                .stat(path)
-                .await
-                .map_err(|err| Error::from(err))?
-                .content_length()
-        } else {
-            0
-        };
-
-        let inner_writer = InnerWriter { writer, written };
-        let mut opened_file_writer = self.opened_files_writer.lock().unwrap();
-        opened_file_writer.insert(path.to_string(), inner_writer);
+        // the frontend accepts a limited number of ranges per request.
+        for chunk in mappings.chunks(VHOST_USER_FS_BACKEND_ENTRIES) {
+            let mut msg = VhostUserFSBackendMsg::default();
+            for (i, mapping) in chunk.iter().enumerate() {
+                msg.cache_offset[i] = mapping.moffset;
+                msg.len[i] = mapping.len;
+            }
+            if backend_req.fs_backend_unmap(&msg).is_err() {
+                return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+            }
+        }
 
-        Ok(())
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
     }
 
-    async fn do_release_writer(&self, path: &str) -> Result<()> {
-        let mut opened_file_writer = self.opened_files_writer.lock().unwrap();
-        let inner_writer = opened_file_writer
-            .get_mut(path)
-            .ok_or(Error::from(libc::EIO))?;
-        inner_writer
-            .writer
-            .close()
-            .await
-            .map_err(|err| Error::from(err))?;
-        opened_file_writer.remove(path);
+    fn statfs(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
+        debug!("statfs: inode={}", in_header.nodeid);
 
-        Ok(())
+        if self.opened_file(in_header.nodeid).is_none() {
+            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
+        }
+
+        // OpenDAL does not expose backend usage, so report a large and mostly free filesystem.
+        let out = StatfsOut {
+            st: Kstatfs {
+                blocks: STATFS_DEFAULT_BLOCKS,
+                bfree: STATFS_DEFAULT_BLOCKS,
+                bavail: STATFS_DEFAULT_BLOCKS,
+                files: STATFS_DEFAULT_FILES,
+                ffree: STATFS_DEFAULT_FILES,
+                bsize: STATFS_BLOCK_SIZE,
+                namelen: MAX_NAME_LEN,
+                frsize: STATFS_BLOCK_SIZE,
+                ..Default::default()
+            },
+        };
+        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
     }
 
-    async fn do_delete(&self, path: &str) -> Result<()> {
-        self.core
-            .delete(path)
-            .await
-            .map_err(|err| Error::from(err))?;
+    fn mkdir(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let MkdirIn { mode, umask } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
 
-        Ok(())
-    }
+        let name_len = match Filesystem::payload_len(&in_header, size_of::<MkdirIn>()) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
+        let mut buf = vec![0; name_len];
+        r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
+        let name = match Filesystem::bytes_to_str(buf.as_ref()) {
+            Ok(name) if Filesystem::is_name_too_long(name) => {
+                return Filesystem::reply_error(in_header.unique, w, libc::ENAMETOOLONG)
+            }
+            Ok(name) if Filesystem::is_entry_name(name) => name,
+            Ok(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
+        };
 
-    async fn do_read(&self, path: &str, offset: u64) -> Result<Buffer> {
-        let data = self
-            .core
-            .read_with(path)
-            .range(offset..)
-            .await
-            .map_err(|err| Error::from(err))?;
+        debug!("mkdir: parent inode={} name={}", in_header.nodeid, name);
 
-        Ok(data)
-    }
+        let parent_path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
 
-    async fn do_write(&self, path: &str, offset: u64, data: Buffer) -> Result<usize> {
-        let len = data.len();
-        let mut opened_file_writer = self.opened_files_writer.lock().unwrap();
-        let inner_writer = opened_file_writer
-            .get_mut(path)
-            .ok_or(Error::from(libc::EIO))?;
-        if offset != inner_writer.written {
-            return Err(Error::from(libc::EIO));
+        let path = Filesystem::join_path(&parent_path, name);
+        match self.rt.block_on(self.do_get_entry_mode(&path)) {
+            Some(opendal::EntryMode::DIR) => {
+                return Filesystem::reply_error(in_header.unique, w, libc::EEXIST)
+            }
+            Some(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOTDIR),
+            None => {}
         }
-        inner_writer
-            .writer
-            .write_from(data)
-            .await
-            .map_err(|err| Error::from(err))?;
-        inner_writer.written += len as u64;
 
-        Ok(len)
-    }
+        // the inode only exists once the backend has the directory.
+        if let Err(err) = self.rt.block_on(self.do_create_dir(&path)) {
+            let errno = Filesystem::backend_errno(&err, libc::EIO);
+            return Filesystem::reply_error(in_header.unique, w, errno);
+        }
+        self.invalidate_negative_lookup(in_header.nodeid, name);
+        self.invalidate_dir_cache(&parent_path);
+        let mut attr = OpenedFile::new(FileType::Dir, &path);
+        attr.set_permissions(mode & !umask);
+        attr.nlookup = 1;
+        let mut opened_files_map = self.opened_files_map.lock().unwrap();
+        self.insert_inode(&mut opened_files_map, &mut attr);
+        drop(opened_files_map);
+        self.store_local_mode(&path, attr.metadata.mode);
+
+        let out = EntryOut {
+            nodeid: attr.metadata.ino,
+            generation: attr.generation,
+            entry_valid: self.entry_timeout.as_secs(),
+            attr_valid: self.attr_timeout.as_secs(),
+            entry_valid_nsec: self.entry_timeout.subsec_nanos(),
+            attr_valid_nsec: self.attr_timeout.subsec_nanos(),
+            attr: self.owned_attr(&in_header, &attr),
+        };
+        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
+    }
+
+    fn rmdir(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let name_len = match Filesystem::payload_len(&in_header, 0) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
+        let mut buf = vec![0; name_len];
+        r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
+        let name = match Filesystem::bytes_to_str(buf.as_ref()) {
+            Ok(name) if Filesystem::is_name_too_long(name) => {
+                return Filesystem::reply_error(in_header.unique, w, libc::ENAMETOOLONG)
+            }
+            Ok(name) if Filesystem::is_entry_name(name) => name,
+            Ok(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
+        };
+
+        debug!("rmdir: parent inode={} name={}", in_header.nodeid, name);
+
+        let parent_path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let path = self.rt.block_on(self.do_resolve_name(&parent_path, name));
+        // deleting the name without its trailing slash would leave an object store's marker.
+        if self
+            .rt
+            .block_on(self.do_delete(&format!("{}/", path)))
+            .is_err()
+        {
+            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
+        }
+        self.invalidate_dir_cache(&parent_path);
+
+        let mut opened_files_map = self.opened_files_map.lock().unwrap();
+        opened_files_map.remove(&path);
+
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
+    }
+
+    fn rename(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let RenameIn { newdir } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        self.rename_entry(in_header, newdir, 0, size_of::<RenameIn>(), r, w)
+    }
+
+    fn rename2(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let Rename2In { newdir, flags, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        self.rename_entry(in_header, newdir, flags, size_of::<Rename2In>(), r, w)
+    }
+
+    fn rename_entry(
+        &self,
+        in_header: InHeader,
+        newdir: u64,
+        flags: u32,
+        fixed: usize,
+        mut r: Reader,
+        w: Writer,
+    ) -> Result<usize> {
+        let name_len = match Filesystem::payload_len(&in_header, fixed) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
+        let mut buf = vec![0; name_len];
+        r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
+        let (old_name, new_name) = match Filesystem::bytes_to_str_pair(buf.as_ref()) {
+            Ok((old_name, new_name))
+                if Filesystem::is_name_too_long(old_name)
+                    || Filesystem::is_name_too_long(new_name) =>
+            {
+                return Filesystem::reply_error(in_header.unique, w, libc::ENAMETOOLONG)
+            }
+            Ok((old_name, new_name))
+                if Filesystem::is_entry_name(old_name) && Filesystem::is_entry_name(new_name) =>
+            {
+                (old_name, new_name)
+            }
+            Ok(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
+        };
+
+        debug!(
+            "rename: parent inode={} name={} new parent inode={} new name={} flags={}",
+            in_header.nodeid, old_name, newdir, new_name, flags
+        );
+
+        let noreplace = flags == libc::RENAME_NOREPLACE;
+        let exchange = flags == libc::RENAME_EXCHANGE;
+        // whiteouts only make sense for overlay filesystems, and the two flags exclude each other.
+        if flags != 0 && !noreplace && !exchange {
+            return Filesystem::reply_error(in_header.unique, w, libc::EINVAL);
+        }
+
+        let old_parent_path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+        let new_parent_path = match self
+            .opened_file(newdir)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let capability = self.core().info().full_capability();
+        if !capability.rename && !capability.copy {
+            return Filesystem::reply_error(in_header.unique, w, libc::EXDEV);
+        }
+
+        let old_path = self
+            .rt
+            .block_on(self.do_resolve_name(&old_parent_path, old_name));
+        let new_path = Filesystem::join_path(&new_parent_path, new_name);
+        let new_exists = self
+            .rt
+            .block_on(self.do_get_entry_mode(&new_path))
+            .is_some();
+        if noreplace && new_exists {
+            return Filesystem::reply_error(in_header.unique, w, libc::EEXIST);
+        }
+        if exchange && !new_exists {
+            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
+        }
+
+        if let Err(err) = self
+            .rt
+            .block_on(self.do_release_rename_writers(&old_path, &new_path))
+        {
+            let errno = Filesystem::backend_errno(&err, libc::EIO);
+            return Filesystem::reply_error(in_header.unique, w, errno);
+        }
+
+        let result = if exchange {
+            let tmp_name = format!(".ovfs-exchange-{}", in_header.unique);
+            let tmp_path = Filesystem::join_path(&new_parent_path, &tmp_name);
+            self.rt
+                .block_on(self.do_exchange(&old_path, &new_path, &tmp_path))
+        } else {
+            self.rt.block_on(self.do_rename(&old_path, &new_path))
+        };
+        if let Err(err) = result {
+            let errno = err.errno().unwrap_or(libc::EIO);
+            return Filesystem::reply_error(in_header.unique, w, errno);
+        }
+
+        // a renamed directory takes every inode below it along.
+        let mut opened_files_map = self.opened_files_map.lock().unwrap();
+        let old_tree = self.take_tree(&mut opened_files_map, &old_path);
+        let new_tree = self.take_tree(&mut opened_files_map, &new_path);
+        self.place_tree(&mut opened_files_map, old_tree, &new_path);
+        if exchange {
+            self.place_tree(&mut opened_files_map, new_tree, &old_path);
+        }
+        drop(opened_files_map);
+        self.invalidate_tree_caches(&old_path);
+        self.invalidate_tree_caches(&new_path);
+        self.invalidate_negative_lookup(newdir, new_name);
+        self.invalidate_dir_cache(&old_parent_path);
+        self.invalidate_dir_cache(&new_parent_path);
+
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
+    }
+
+    fn symlink(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let name_len = match Filesystem::payload_len(&in_header, 0) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
+        let mut buf = vec![0; name_len];
+        r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
+        let (name, target) = match Filesystem::bytes_to_str_pair(buf.as_ref()) {
+            Ok((name, _)) if Filesystem::is_name_too_long(name) => {
+                return Filesystem::reply_error(in_header.unique, w, libc::ENAMETOOLONG)
+            }
+            Ok((name, target)) if Filesystem::is_entry_name(name) => (name, target),
+            Ok(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
+        };
+
+        debug!(
+            "symlink: parent inode={} name={} target={}",
+            in_header.nodeid, name, target
+        );
+
+        // symlinks are stored as regular objects marked by user metadata, so the
+        // backend must be able to persist it.
+        if !self
+            .core()
+            .info()
+            .full_capability()
+            .write_with_user_metadata
+        {
+            return Filesystem::reply_error(in_header.unique, w, libc::EPERM);
+        }
+
+        let parent_path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let path = Filesystem::join_path(&parent_path, name);
+        if self.rt.block_on(self.do_symlink(&path, target)).is_err() {
+            return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+        }
+        self.invalidate_negative_lookup(in_header.nodeid, name);
+        self.invalidate_dir_cache(&parent_path);
+
+        let mut attr = OpenedFile::new(FileType::Symlink, &path);
+        attr.set_size(target.len() as u64);
+        attr.nlookup = 1;
+        let mut opened_files_map = self.opened_files_map.lock().unwrap();
+        self.insert_inode(&mut opened_files_map, &mut attr);
+
+        let out = EntryOut {
+            nodeid: attr.metadata.ino,
+            generation: attr.generation,
+            entry_valid: self.entry_timeout.as_secs(),
+            attr_valid: self.attr_timeout.as_secs(),
+            entry_valid_nsec: self.entry_timeout.subsec_nanos(),
+            attr_valid_nsec: self.attr_timeout.subsec_nanos(),
+            attr: self.owned_attr(&in_header, &attr),
+        };
+        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
+    }
+
+    fn link(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let LinkIn { oldnodeid } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        let name_len = match Filesystem::payload_len(&in_header, size_of::<LinkIn>()) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
+        let mut buf = vec![0; name_len];
+        r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
+        let name = match Filesystem::bytes_to_str(buf.as_ref()) {
+            Ok(name) if Filesystem::is_name_too_long(name) => {
+                return Filesystem::reply_error(in_header.unique, w, libc::ENAMETOOLONG)
+            }
+            Ok(name) if Filesystem::is_entry_name(name) => name,
+            Ok(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
+        };
+
+        debug!(
+            "link: inode={} new parent inode={} new name={}",
+            oldnodeid, in_header.nodeid, name
+        );
+
+        let old_path = match self
+            .opened_file(oldnodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+        let parent_path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let path = Filesystem::join_path(&parent_path, name);
+        if self.rt.block_on(self.do_link(&old_path, &path)).is_err() {
+            return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+        }
+        self.invalidate_negative_lookup(in_header.nodeid, name);
+        self.invalidate_dir_cache(&parent_path);
+
+        let metadata = match self.rt.block_on(self.do_get_metadata(&path, true)) {
+            Ok(metadata) => metadata,
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let out = EntryOut {
+            nodeid: metadata.metadata.ino,
+            generation: metadata.generation,
+            entry_valid: self.entry_timeout.as_secs(),
+            attr_valid: self.attr_timeout.as_secs(),
+            entry_valid_nsec: self.entry_timeout.subsec_nanos(),
+            attr_valid_nsec: self.attr_timeout.subsec_nanos(),
+            attr: self.owned_attr(&in_header, &metadata),
+        };
+        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
+    }
+
+    fn readlink(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
+        debug!("readlink: inode={}", in_header.nodeid);
+
+        let path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let target = match self.rt.block_on(self.do_readlink(&path)) {
+            Ok(target) => target,
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
+
+        Filesystem::reply_ok(None::<u8>, Some(&target), in_header.unique, w)
+    }
+
+    fn getxattr(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let GetxattrIn { size, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+        let name_len = match Filesystem::payload_len(&in_header, size_of::<GetxattrIn>()) {
+            Some(len) => len,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
+        let mut buf = vec![0; name_len];
+        r.read_exact(&mut buf).map_err(|_| Error::from(libc::EIO))?;
+        let name = match Filesystem::bytes_to_str(buf.as_ref()) {
+            Ok(name) => name,
+            Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EINVAL),
+        };
+
+        debug!(
+            "getxattr: inode={} name={} size={}",
+            in_header.nodeid, name, size
+        );
+
+        // the ETag is the only attribute, and a file unlinked while open has none left.
+        if name != ETAG_XATTR_NAME || self.with_orphan(in_header.nodeid, |_| ()).is_some() {
+            return Filesystem::reply_error(in_header.unique, w, libc::ENODATA);
+        }
+
+        let path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let etag = match self.rt.block_on(self.do_get_etag(&path)) {
+            Ok(Some(etag)) => etag,
+            Ok(None) => return Filesystem::reply_error(in_header.unique, w, libc::ENODATA),
+            Err(err) => {
+                let errno = Filesystem::backend_errno(&err, libc::ENOENT);
+                return Filesystem::reply_error(in_header.unique, w, errno);
+            }
+        };
+
+        // a zero size asks for the length of the value only.
+        if size == 0 {
+            let out = GetxattrOut {
+                size: etag.len() as u32,
+                padding: 0,
+            };
+            return Filesystem::reply_ok(Some(out), None, in_header.unique, w);
+        }
+        if etag.len() > size as usize {
+            return Filesystem::reply_error(in_header.unique, w, libc::ERANGE);
+        }
+
+        Filesystem::reply_ok(None::<u8>, Some(etag.as_bytes()), in_header.unique, w)
+    }
+
+    fn releasedir(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let ReleaseIn { fh, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!("releasedir: inode={} fh={}", in_header.nodeid, fh);
+
+        if !self.remove_handle(fh) {
+            return Filesystem::reply_error(in_header.unique, w, libc::EBADF);
+        }
+
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
+    }
+
+    fn fsyncdir(&self, in_header: InHeader, _r: Reader, w: Writer) -> Result<usize> {
+        debug!("fsyncdir: inode={}", in_header.nodeid);
+
+        let path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        // commit every object still being written below the directory so a listing sees it.
+        if self
+            .rt
+            .block_on(self.do_release_dir_writers(&path))
+            .is_err()
+        {
+            return Filesystem::reply_error(in_header.unique, w, libc::EIO);
+        }
+
+        Filesystem::reply_ok(None::<u8>, None, in_header.unique, w)
+    }
+
+    fn opendir(&self, in_header: InHeader, mut r: Reader, w: Writer) -> Result<usize> {
+        let OpenIn { flags, .. } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!("opendir: inode={}", in_header.nodeid);
+
+        if self.opened_file(in_header.nodeid).is_none() {
+            return Filesystem::reply_error(in_header.unique, w, libc::ENOENT);
+        }
+
+        let out = OpenOut {
+            fh: self.insert_handle(in_header.nodeid, flags),
+            ..Default::default()
+        };
+        Filesystem::reply_ok(Some(out), None, in_header.unique, w)
+    }
+
+    fn readdir(&self, in_header: InHeader, r: Reader, w: Writer) -> Result<usize> {
+        self.read_dir_entries(in_header, r, w, false)
+    }
+
+    fn readdirplus(&self, in_header: InHeader, r: Reader, w: Writer) -> Result<usize> {
+        self.read_dir_entries(in_header, r, w, true)
+    }
+
+    fn read_dir_entries(
+        &self,
+        in_header: InHeader,
+        mut r: Reader,
+        mut w: Writer,
+        plus: bool,
+    ) -> Result<usize> {
+        let path = match self
+            .opened_file(in_header.nodeid)
+            .map(|f| f.read().unwrap().path.clone())
+        {
+            Some(path) => path,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::ENOENT),
+        };
+
+        let ReadIn {
+            fh, offset, size, ..
+        } = r.read_obj().map_err(|_| Error::from(libc::EIO))?;
+
+        debug!(
+            "{}: inode={} fh={} offset={} size={}",
+            if plus { "readdirplus" } else { "readdir" },
+            in_header.nodeid,
+            fh,
+            offset,
+            size
+        );
+
+        let handle = match self.opened_handles.get(fh as usize) {
+            Some(handle) => handle,
+            None => return Filesystem::reply_error(in_header.unique, w, libc::EBADF),
+        };
+
+        let mut data_writer = w.split_at(size_of::<OutHeader>()).unwrap();
+
+        let mut handle = handle.write().unwrap();
+        // a page resumes where the previous one ended, rewinding or seeking lists again.
+        let resumable = match &handle.listing {
+            Some(DirListing::Cached(_)) => offset != 0,
+            Some(DirListing::Stream(stream)) => offset != 0 && stream.position() == offset,
+            None => false,
+        };
+        if !resumable {
+            let cached_listing = self.dir_cache.lock().unwrap().get(&path);
+            let listing = match cached_listing {
+                Some(entries) => DirListing::Cached(entries),
+                None => match self.rt.block_on(self.do_open_dir_stream(&path)) {
+                    Ok(stream) => DirListing::Stream(Box::new(stream)),
+                    Err(err) => {
+                        let errno = Filesystem::backend_errno(&err, libc::ENOENT);
+                        return Filesystem::reply_error(in_header.unique, w, errno);
+                    }
+                },
+            };
+            handle.listing = Some(listing);
+        }
+
+        let mut page = Vec::new();
+        let total_written = match handle.listing.as_mut() {
+            Some(DirListing::Cached(entries)) => {
+                let mut total_written = 0;
+                for entry in entries.iter().skip(offset as usize) {
+                    if total_written + Filesystem::dir_entry_len(entry, plus) > size as usize {
+                        break;
+                    }
+                    let entry_out = plus.then(|| self.lookup_dir_entry(&in_header, &path, entry));
+                    match Filesystem::reply_add_dir_entry(&mut data_writer, entry, entry_out) {
+                        Ok(len) => {
+                            total_written += len;
+                        }
+                        Err(_) => return Filesystem::reply_error(in_header.unique, w, libc::EIO),
+                    };
+                    page.push(entry.clone());
+                }
+                total_written
+            }
+            Some(DirListing::Stream(stream)) => {
+                match self.rt.block_on(self.do_fill_dir_page(
+                    &in_header,
+                    plus,
+                    stream,
+                    offset,
+                    size as usize,
+                    &mut data_writer,
+                )) {
+                    Ok((total_written, entries)) => {
+                        page = entries;
+                        total_written
+                    }
+                    Err(err) => {
+                        let errno = Filesystem::backend_errno(&err, libc::EIO);
+                        return Filesystem::reply_error(in_header.unique, w, errno);
+                    }
+                }
+            }
+            None => 0,
+        };
+        drop(handle);
+
+        if self.prefetch_stats > 0 {
+            self.rt.block_on(self.do_prefetch_stats(&path, &page));
+        }
+
+        let out = OutHeader {
+            len: (size_of::<OutHeader>() + total_written) as u32,
+            error: 0,
+            unique: in_header.unique,
+        };
+
+        w.write_all(out.as_slice())
+            .map_err(|_| Error::from(libc::EIO))?;
+        Ok(out.len as usize)
+    }
+}
+
+impl Filesystem {
+    fn reply_ok<T: ByteValued>(
+        out: Option<T>,
+        data: Option<&[u8]>,
+        unique: u64,
+        mut w: Writer,
+    ) -> Result<usize> {
+        let mut len = size_of::<OutHeader>();
+        if out.is_some() {
+            len += size_of::<T>();
+        }
+        if let Some(data) = data {
+            len += data.len();
+        }
+        let header = OutHeader {
+            unique,
+            error: 0,
+            len: len as u32,
+        };
+        w.write_all(header.as_slice())
+            .map_err(|_| Error::from(libc::EIO))?;
+        if let Some(out) = out {
+            w.write_all(out.as_slice())
+                .map_err(|_| Error::from(libc::EIO))?;
+        }
+        if let Some(data) = data {
+            w.write_all(data).map_err(|_| Error::from(libc::EIO))?;
+        }
+        Ok(w.bytes_written())
+    }
+
+    fn dir_entry_header_len(plus: bool) -> usize {
+        if plus {
+            size_of::<DirEntryPlusOut>()
+        } else {
+            size_of::<DirEntryOut>()
+        }
+    }
+
+    fn dir_entry_len(entry: &DirEntry, plus: bool) -> usize {
+        (Filesystem::dir_entry_header_len(plus) + entry.name.len() + 7) & !7
+    }
+
+    // readdirplus entries carry the attributes of the entry ahead of the plain one.
+    fn reply_add_dir_entry(
+        cursor: &mut Writer,
+        entry: &DirEntry,
+        entry_out: Option<EntryOut>,
+    ) -> Result<usize> {
+        let plus = entry_out.is_some();
+        let entry_len = Filesystem::dir_entry_header_len(plus) + entry.name.len();
+        let total_len = Filesystem::dir_entry_len(entry, plus);
+
+        let dirent = DirEntryOut {
+            ino: entry.ino,
+            off: entry.off,
+            namelen: entry.name.len() as u32,
+            type_: entry.type_,
+        };
+
+        match entry_out {
+            Some(entry_out) => cursor.write_all(DirEntryPlusOut { entry_out, dirent }.as_slice()),
+            None => cursor.write_all(dirent.as_slice()),
+        }
+        .map_err(|_| Error::from(libc::EIO))?;
+        cursor
+            .write_all(entry.name.as_bytes())
+            .map_err(|_| Error::from(libc::EIO))?;
+
+        let padding = total_len - entry_len;
+        if padding > 0 {
+            cursor
+                .write_all(&DIRENT_PADDING[..padding])
+                .map_err(|_| Error::from(libc::EIO))?;
+        }
+
+        Ok(total_len)
+    }
+
+    fn reply_error(unique: u64, mut w: Writer, error: libc::c_int) -> Result<usize> {
+        metrics::mark_error();
+        let header = OutHeader {
+            unique,
+            error: -error,
+            len: size_of::<OutHeader>() as u32,
+        };
+        w.write_all(header.as_slice())
+            .map_err(|_| Error::from(libc::EIO))?;
+        Ok(w.bytes_written())
+    }
+
+    // length of the data following the header and the fixed size request struct, which
+    // is missing when the guest sent a request shorter than both.
+    fn payload_len(in_header: &InHeader, fixed: usize) -> Option<usize> {
+        (in_header.len as usize).checked_sub(size_of::<InHeader>() + fixed)
+    }
+
+    fn bytes_to_str(buf: &[u8]) -> Result<&str> {
+        std::str::from_utf8(Filesystem::bytes_to_name(buf)?).map_err(|_| Error::from(libc::EINVAL))
+    }
+
+    fn bytes_to_str_pair(buf: &[u8]) -> Result<(&str, &str)> {
+        let pos = buf
+            .iter()
+            .position(|c| *c == 0)
+            .ok_or(Error::from(libc::EINVAL))?;
+        let (first, second) = buf.split_at(pos + 1);
+        Ok((
+            Filesystem::bytes_to_str(first)?,
+            Filesystem::bytes_to_str(second)?,
+        ))
+    }
+
+    /// Collapses repeated slashes and drops the leading one, the form OpenDAL keys take.
+    /// The root stays `/`.
+    fn normalize_path(path: &str) -> String {
+        let path = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+        if path.is_empty() {
+            "/".to_string()
+        } else {
+            path
+        }
+    }
+
+    fn join_path(parent: &str, name: &str) -> String {
+        Filesystem::normalize_path(&format!("{}/{}", parent, name))
+    }
+
+    fn is_within(path: &str, root: &str) -> bool {
+        path == root
+            || root == "/"
+            || path
+                .strip_prefix(root)
+                .is_some_and(|rest| rest.starts_with('/'))
+    }
+
+    // top-level entries and the root itself have the root as their parent.
+    fn parent_path(path: &str) -> &str {
+        match path.rsplit_once('/') {
+            Some((parent, _)) if !parent.is_empty() => parent,
+            _ => "/",
+        }
+    }
+
+    // a name must stay a single component of its parent, so it can never climb above the root.
+    // names are checked in bytes, like NAME_MAX on a local file system.
+    fn is_name_too_long(name: &str) -> bool {
+        name.len() > MAX_NAME_LEN as usize
+    }
+
+    fn is_entry_name(name: &str) -> bool {
+        !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+    }
+
+    fn bytes_to_name(buf: &[u8]) -> Result<&[u8]> {
+        // names end at the first NUL, anything after it must be zero padding.
+        let (name, padding) = match buf.iter().position(|c| *c == 0) {
+            Some(pos) => buf.split_at(pos),
+            None => (buf, &[][..]),
+        };
+        if padding.iter().any(|c| *c != 0) {
+            return Err(Error::from(libc::EINVAL));
+        }
+        Ok(name)
+    }
+
+    fn core(&self) -> Operator {
+        self.core.read().unwrap().clone()
+    }
+
+    /// Content type an object written to `path` is stored with, if the backend keeps one.
+    fn content_type(&self, path: &str) -> Option<&str> {
+        let content_type = self.content_types.as_ref()?.infer(path)?;
+        if !self.core().info().full_capability().write_with_content_type {
+            return None;
+        }
+        debug!("content type: path={} type={}", path, content_type);
+        Some(content_type)
+    }
+
+    // owners are only stored by chown, others are filled in when attributes are sent to the guest.
+    fn owned_attr(&self, in_header: &InHeader, file: &OpenedFile) -> Attr {
+        let mut attr = file.metadata;
+        if let Some((uid, gid)) = file.owner {
+            attr.uid = uid;
+            attr.gid = gid;
+        } else if self.passthrough_owner {
+            attr.uid = in_header.uid;
+            attr.gid = in_header.gid;
+        } else {
+            attr.uid = self.uid;
+            attr.gid = self.gid;
+        }
+        attr
+    }
+
+    fn object_metadata(file: &OpenedFile) -> Vec<(String, String)> {
+        let mut metadata = vec![(
+            MODE_METADATA_KEY.to_string(),
+            format!("{:o}", file.metadata.mode & PERMISSION_MODE_MASK),
+        )];
+        if let Some((uid, gid)) = file.owner {
+            metadata.push((UID_METADATA_KEY.to_string(), uid.to_string()));
+            metadata.push((GID_METADATA_KEY.to_string(), gid.to_string()));
+        }
+        metadata
+    }
+
+    /// Removes the inodes of a path and everything below it from the map, keyed by their path
+    /// relative to it.
+    fn take_tree(
+        &self,
+        opened_files_map: &mut HashMap<String, u64>,
+        root: &str,
+    ) -> Vec<(String, u64)> {
+        let paths: Vec<String> = opened_files_map
+            .keys()
+            .filter(|path| Filesystem::is_within(path, root))
+            .cloned()
+            .collect();
+        paths
+            .into_iter()
+            .filter_map(|path| {
+                let inode = opened_files_map.remove(&path)?;
+                Some((path[root.len()..].to_string(), inode))
+            })
+            .collect()
+    }
+
+    /// Maps inodes taken by `take_tree` again under `root`, updating the paths they keep.
+    fn place_tree(
+        &self,
+        opened_files_map: &mut HashMap<String, u64>,
+        tree: Vec<(String, u64)>,
+        root: &str,
+    ) {
+        for (relative, inode) in tree {
+            let path = format!("{}{}", root, relative);
+            if let Some(file) = self.opened_file(inode) {
+                file.write().unwrap().path = path.clone();
+            }
+            opened_files_map.insert(path, inode);
+        }
+    }
+
+    fn cached_object_metadata(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let inode = *self.opened_files_map.lock().unwrap().get(path)?;
+        let file = self.opened_file(inode)?;
+        let metadata = Filesystem::object_metadata(&file.read().unwrap());
+        Some(metadata)
+    }
+
+    // inode 0 is invalid in FUSE and the root inode always names the root directory,
+    // whatever slab key it has.
+    fn opened_file(&self, inode: u64) -> Option<Entry<'_, RwLock<OpenedFile>>> {
+        match inode {
+            0 => None,
+            DEFAULT_ROOT_DIR_INODE => self
+                .opened_files
+                .get(self.root_slab_key.load(Ordering::Relaxed)),
+            inode => self.opened_files.get(inode as usize),
+        }
+    }
+
+    // the slots behind inode 0 and the root inode are never freed.
+    fn remove_inode(&self, inode: u64) -> bool {
+        inode > DEFAULT_ROOT_DIR_INODE && self.opened_files.remove(inode as usize)
+    }
+
+    // every inode gets a new generation, so a guest still holding an inode number whose slot
+    // was reused can tell the two files apart.
+    fn insert_inode(
+        &self,
+        opened_files_map: &mut HashMap<String, u64>,
+        attr: &mut OpenedFile,
+    ) -> u64 {
+        let mut entry = self
+            .opened_files
+            .vacant_entry()
+            .expect("failed to allocate inode");
+        // the keys of inode 0 and the root inode are never handed out, their slots stay taken.
+        while entry.key() as u64 <= DEFAULT_ROOT_DIR_INODE {
+            entry.insert(RwLock::new(OpenedFile::new(FileType::Dir, "")));
+            entry = self
+                .opened_files
+                .vacant_entry()
+                .expect("failed to allocate inode");
+        }
+        attr.metadata.ino = entry.key() as u64;
+        attr.generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        entry.insert(RwLock::new(attr.clone()));
+        opened_files_map.insert(attr.path.clone(), attr.metadata.ino);
+
+        if self.max_inodes > 0 {
+            let mut inode_lru = self.inode_lru.lock().unwrap();
+            inode_lru.push_back(attr.metadata.ino);
+            self.evict_inodes(opened_files_map, &mut inode_lru);
+        }
+        attr.metadata.ino
+    }
+
+    // only inodes the guest doesn't reference can go, it would otherwise send requests for
+    // node ids that no longer exist. a later lookup allocates them again.
+    fn evict_inodes(
+        &self,
+        opened_files_map: &mut HashMap<String, u64>,
+        inode_lru: &mut VecDeque<u64>,
+    ) {
+        let mut scanned = 0;
+        while inode_lru.len() > self.max_inodes && scanned < INODE_EVICTION_SCAN_LIMIT {
+            scanned += 1;
+            let Some(inode) = inode_lru.pop_front() else {
+                break;
+            };
+            let path = match self.opened_file(inode) {
+                Some(file) => {
+                    let mut file = file.write().unwrap();
+                    if file.nlookup > 0 || file.opens > 0 || file.recently_used {
+                        file.recently_used = false;
+                        inode_lru.push_back(inode);
+                        continue;
+                    }
+                    file.path.clone()
+                }
+                // already forgotten by the guest.
+                None => continue,
+            };
+            if opened_files_map.get(&path) == Some(&inode) {
+                opened_files_map.remove(&path);
+            }
+            self.remove_inode(inode);
+        }
+    }
+
+    // a full backend, an expired request or a file past the size limit is reported as such,
+    // other failures keep the errno of the operation.
+    fn backend_errno(err: &Error, errno: libc::c_int) -> libc::c_int {
+        match err.errno() {
+            Some(libc::EFBIG) => libc::EFBIG,
+            Some(libc::ENOSPC) => libc::ENOSPC,
+            Some(libc::EDQUOT) => libc::EDQUOT,
+            Some(libc::ETIMEDOUT) => libc::ETIMEDOUT,
+            _ => errno,
+        }
+    }
+
+    /// Fails with EFBIG when a file rewritten in memory would grow past `max_file_size`.
+    fn check_file_size(&self, size: u64) -> Result<()> {
+        if size > self.max_file_size {
+            return Err(Error::from(libc::EFBIG));
+        }
+        Ok(())
+    }
+
+    fn insert_handle(&self, inode: u64, flags: u32) -> u64 {
+        if let Some(file) = self.opened_file(inode) {
+            file.write().unwrap().opens += 1;
+        }
+        let handle = OpenedHandle {
+            inode,
+            flags,
+            listing: None,
+        };
+        self.opened_handles_count.fetch_add(1, Ordering::Relaxed);
+        self.opened_handles
+            .insert(RwLock::new(handle))
+            .expect("failed to allocate file handle") as u64
+    }
+
+    fn remove_handle(&self, fh: u64) -> bool {
+        let Some(handle) = self.opened_handles.take(fh as usize) else {
+            return false;
+        };
+        self.opened_handles_count.fetch_sub(1, Ordering::Relaxed);
+        let inode = handle.into_inner().unwrap().inode;
+        if let Some(file) = self.opened_file(inode) {
+            let mut file = file.write().unwrap();
+            file.opens = file.opens.saturating_sub(1);
+            if file.opens == 0 {
+                self.orphans.lock().unwrap().remove(&inode);
+            }
+        }
+        true
+    }
+
+    /// Runs `f` on the contents of an inode unlinked while still open, `None` for any other.
+    fn with_orphan<T>(&self, inode: u64, f: impl FnOnce(&mut Vec<u8>) -> T) -> Option<T> {
+        self.orphans.lock().unwrap().get_mut(&inode).map(f)
+    }
+
+    /// Attributes of an inode, the cached ones for a file unlinked while still open.
+    fn get_inode_metadata(&self, inode: u64, path: &str) -> Result<OpenedFile> {
+        if self.with_orphan(inode, |_| ()).is_some() {
+            return self
+                .opened_file(inode)
+                .map(|f| f.read().unwrap().clone())
+                .ok_or(Error::from(libc::ENOENT));
+        }
+
+        // an inode whose path left the map is linked to it again, so the stat refreshes this
+        // inode instead of allocating another one for the path.
+        let relinked = {
+            let mut opened_files_map = self.opened_files_map.lock().unwrap();
+            !opened_files_map.contains_key(path)
+                && opened_files_map.insert(path.to_string(), inode).is_none()
+        };
+        let result = self.rt.block_on(self.do_get_metadata(path, false));
+        if relinked && result.is_err() {
+            let mut opened_files_map = self.opened_files_map.lock().unwrap();
+            if opened_files_map.get(path) == Some(&inode) {
+                opened_files_map.remove(path);
+            }
+        }
+        result
+    }
+
+    fn is_direct_io(flags: u32) -> bool {
+        flags & libc::O_DIRECT as u32 != 0
+    }
+
+    /// Flags of the reply to an open, O_DIRECT keeps the guest's page cache out of the way.
+    fn open_flags(flags: u32) -> u32 {
+        if Filesystem::is_direct_io(flags) {
+            FOPEN_DIRECT_IO
+        } else {
+            0
+        }
+    }
+
+    fn handle_direct_io(&self, fh: u64) -> bool {
+        self.opened_handles
+            .get(fh as usize)
+            .is_some_and(|h| Filesystem::is_direct_io(h.read().unwrap().flags))
+    }
+
+    fn handle_writes(&self, fh: u64) -> bool {
+        self.opened_handles.get(fh as usize).is_some_and(|h| {
+            h.read().unwrap().flags & libc::O_ACCMODE as u32 != libc::O_RDONLY as u32
+        })
+    }
+
+    fn handle_inode(&self, fh: u64) -> Option<u64> {
+        self.opened_handles
+            .get(fh as usize)
+            .map(|h| h.read().unwrap().inode)
+    }
+
+    // an entry returned with attributes counts as a lookup, the guest forgets it like one. a
+    // zero node id leaves the guest to look the entry up itself, which is needed for inodes
+    // evicted since the listing and for directories whose subdirectories are counted on stat.
+    fn lookup_dir_entry(&self, in_header: &InHeader, dir_path: &str, entry: &DirEntry) -> EntryOut {
+        let child_path = Filesystem::join_path(dir_path, &entry.name);
+        let opened_files_map = self.opened_files_map.lock().unwrap();
+        let file = opened_files_map
+            .get(&child_path)
+            .and_then(|inode| self.opened_file(*inode));
+        let Some(file) = file else {
+            return EntryOut::default();
+        };
+        let mut file = file.write().unwrap();
+        if self.count_subdirs && file.metadata.mode & libc::S_IFMT == libc::S_IFDIR {
+            return EntryOut::default();
+        }
+        // listings report the compressed length, so files are looked up on their own.
+        if self.compression.is_some() && file.metadata.mode & libc::S_IFMT == libc::S_IFREG {
+            return EntryOut::default();
+        }
+        file.nlookup += 1;
+        file.recently_used = true;
+
+        EntryOut {
+            nodeid: file.metadata.ino,
+            generation: file.generation,
+            entry_valid: self.entry_timeout.as_secs(),
+            attr_valid: self.attr_timeout.as_secs(),
+            entry_valid_nsec: self.entry_timeout.subsec_nanos(),
+            attr_valid_nsec: self.attr_timeout.subsec_nanos(),
+            attr: self.owned_attr(in_header, &file),
+        }
+    }
+
+    /// Codec new objects are compressed with, none when the backend can't store the marker.
+    fn write_compression(&self) -> Option<Compression> {
+        self.compression.filter(|_| {
+            self.core()
+                .info()
+                .full_capability()
+                .write_with_user_metadata
+        })
+    }
+
+    fn stored_compression(&self, path: &str) -> Option<Compression> {
+        let inode = *self.opened_files_map.lock().unwrap().get(path)?;
+        let file = self.opened_file(inode)?;
+        let compression = file.read().unwrap().compression;
+        compression
+    }
+
+    fn set_stored_compression(&self, path: &str, compression: Option<Compression>) {
+        let opened_files_map = self.opened_files_map.lock().unwrap();
+        if let Some(file) = opened_files_map
+            .get(path)
+            .and_then(|inode| self.opened_file(*inode))
+        {
+            file.write().unwrap().compression = compression;
+        }
+    }
+
+    fn decompress_object(&self, path: &str, data: Buffer) -> Result<Buffer> {
+        let Some(compression) = self.stored_compression(path) else {
+            return Ok(data);
+        };
+        match compression.decompress(&data.to_bytes()) {
+            Ok(data) => Ok(Buffer::from(data)),
+            Err(err) => {
+                warn!("failed to decompress {}: {}", path, err);
+                Err(Error::from(libc::EIO))
+            }
+        }
+    }
+
+    /// Host path of an entry on the fs service, which keeps modes on disk.
+    fn local_path(&self, path: &str) -> Option<PathBuf> {
+        let info = self.core().info();
+        (info.scheme() == opendal::Scheme::Fs)
+            .then(|| Path::new(info.root()).join(path.trim_start_matches('/')))
+    }
+
+    fn local_mode(&self, path: &str) -> Option<u32> {
+        let metadata = symlink_metadata(self.local_path(path)?).ok()?;
+        Some(metadata.permissions().mode() & PERMISSION_MODE_MASK)
+    }
+
+    /// Applies a mode on disk on the fs service, other backends have nowhere to apply it.
+    fn set_local_mode(&self, path: &str, mode: u32) -> io::Result<()> {
+        match self.local_path(path) {
+            Some(local_path) => set_permissions(
+                local_path,
+                Permissions::from_mode(mode & PERMISSION_MODE_MASK),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    /// Like `set_local_mode`, for callers that already succeeded without it.
+    fn store_local_mode(&self, path: &str, mode: u32) {
+        if let Err(err) = self.set_local_mode(path, mode) {
+            warn!("failed to store the mode of {} on disk: {}", path, err);
+        }
+    }
+
+    fn invalidate_read_cache(&self, path: &str) {
+        self.read_cache.lock().unwrap().invalidate(path);
+        // whatever changes the data also changes the stat.
+        self.stat_cache.lock().unwrap().invalidate(path);
+    }
+
+    fn extend_cached_size(&self, inode: u64, end: u64) {
+        if let Some(file) = self.opened_file(inode) {
+            let mut file = file.write().unwrap();
+            let size = max(file.metadata.size, end);
+            file.set_size(size);
+        }
+    }
+
+    fn invalidate_dir_cache(&self, path: &str) {
+        self.dir_cache.lock().unwrap().invalidate(path);
+    }
+
+    /// Drops the cached data, listings and stats of a path and everything below it.
+    fn invalidate_tree_caches(&self, path: &str) {
+        let matches = |p: &str| Filesystem::is_within(p, path);
+        self.read_cache.lock().unwrap().invalidate_matching(matches);
+        self.dir_cache.lock().unwrap().invalidate_matching(matches);
+        self.stat_cache.lock().unwrap().invalidate_matching(matches);
+    }
+
+    fn invalidate_parent_dir_cache(&self, path: &str) {
+        // a newly created object only shows up in its parent's listing once it is closed.
+        self.invalidate_dir_cache(Filesystem::parent_path(path));
+    }
+
+    fn invalidate_negative_lookup(&self, parent: u64, name: &str) {
+        self.negative_lookups.lock().unwrap().remove(parent, name);
+    }
+
+    /// Drops every inode but the root along with the caches referring to them, for a guest
+    /// that mounts again to start over. Returns the number of inodes dropped.
+    fn release_inodes(&self) -> usize {
+        let mut opened_files_map = self.opened_files_map.lock().unwrap();
+        let mut inode_lru = self.inode_lru.lock().unwrap();
+        // unlinked inodes are only left in the eviction queue.
+        let mut inodes: Vec<u64> = opened_files_map
+            .values()
+            .chain(inode_lru.iter())
+            .copied()
+            .filter(|inode| *inode != DEFAULT_ROOT_DIR_INODE)
+            .collect();
+        inodes.sort_unstable();
+        inodes.dedup();
+        let released = inodes
+            .into_iter()
+            .filter(|inode| self.remove_inode(*inode))
+            .count();
+        opened_files_map.retain(|_, inode| *inode == DEFAULT_ROOT_DIR_INODE);
+        inode_lru.clear();
+        drop(inode_lru);
+        drop(opened_files_map);
+
+        self.read_cache.lock().unwrap().clear();
+        self.negative_lookups.lock().unwrap().clear();
+        self.dir_cache.lock().unwrap().clear();
+        self.stat_cache.lock().unwrap().clear();
+        released
+    }
+
+    fn forget_inode(&self, inode: u64, nlookup: u64) {
+        if inode == DEFAULT_ROOT_DIR_INODE {
+            return;
+        }
+        let mut opened_files_map = self.opened_files_map.lock().unwrap();
+        let path = match self.opened_file(inode) {
+            Some(file) => {
+                let mut file = file.write().unwrap();
+                file.nlookup = file.nlookup.saturating_sub(nlookup);
+                if file.nlookup > 0 {
+                    return;
+                }
+                file.path.clone()
+            }
+            None => return,
+        };
+        if opened_files_map.get(&path) == Some(&inode) {
+            opened_files_map.remove(&path);
+        }
+        self.remove_inode(inode);
+    }
+
+    /// Size of the file an object holds, compressed ones record it in their user metadata.
+    fn object_size(metadata: &opendal::Metadata) -> u64 {
+        metadata
+            .user_metadata()
+            .filter(|m| m.contains_key(COMPRESSION_METADATA_KEY))
+            .and_then(|m| m.get(SIZE_METADATA_KEY))
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(metadata.content_length())
+    }
+
+    fn is_symlink(metadata: &opendal::Metadata) -> bool {
+        metadata
+            .user_metadata()
+            .is_some_and(|m| m.contains_key(SYMLINK_METADATA_KEY))
+    }
+
+    fn is_mutating(opcode: &Opcode) -> bool {
+        matches!(
+            opcode,
+            Opcode::Setattr
+                | Opcode::Symlink
+                | Opcode::Link
+                | Opcode::Mkdir
+                | Opcode::Unlink
+                | Opcode::Rmdir
+                | Opcode::Rename
+                | Opcode::Rename2
+                | Opcode::Write
+                | Opcode::Create
+                | Opcode::CopyFileRange
+                | Opcode::Fallocate
+        )
+    }
+
+    fn is_write_flags(flags: u32) -> bool {
+        let mode = flags & libc::O_ACCMODE as u32;
+        mode == libc::O_WRONLY as u32
+            || mode == libc::O_RDWR as u32
+            || flags & (libc::O_APPEND | libc::O_TRUNC | libc::O_CREAT) as u32 != 0
+    }
+
+    fn check_flags(&self, flags: u32) -> Result<(bool, bool, bool)> {
+        let is_trunc = flags & libc::O_TRUNC as u32 != 0;
+        let is_create = flags & libc::O_CREAT as u32 != 0;
+        let is_append = flags & libc::O_APPEND as u32 != 0;
+        let mode = flags & libc::O_ACCMODE as u32;
+        let is_write = mode == libc::O_WRONLY as u32 || mode == libc::O_RDWR as u32 || is_append;
+        // opening only for reading never needs the backend to write.
+        if !is_write && !is_trunc && !is_create {
+            return Ok((false, false, false));
+        }
+
+        let capability = self.core().info().full_capability();
+        if (is_trunc || is_create) && (!capability.write || self.read_only) {
+            Err(Error::from(libc::EACCES))?;
+        }
+        if is_append && (!capability.write_can_append || self.read_only) {
+            Err(Error::from(libc::EACCES))?;
+        }
+        if is_write && !capability.write {
+            Err(Error::from(libc::EACCES))?;
+        }
+        // write-only backends can't read back what was written.
+        if mode == libc::O_RDWR as u32 && !capability.read {
+            Err(Error::from(libc::EOPNOTSUPP))?;
+        }
+        Ok((is_write, is_append, is_trunc && is_write))
+    }
+}
+
+impl Filesystem {
+    async fn do_get_metadata(&self, path: &str, lookup: bool) -> Result<OpenedFile> {
+        let prefetched = self.stat_cache.lock().unwrap().take(path);
+        if prefetched.is_none() {
+            debug!("stat: path={}", path);
+        }
+        let stat = match prefetched {
+            Some(metadata) => Ok(metadata),
+            None => self.core().stat(path).await,
+        };
+        let metadata = match stat {
+            Ok(metadata) => metadata,
+            // object stores, and the memory service, only know a directory by its trailing slash.
+            Err(err) if err.kind() == ErrorKind::NotFound && !path.ends_with('/') => self
+                .core()
+                .stat(&format!("{}/", path))
+                .await
+                .map_err(Error::from)?,
+            Err(err) => return Err(Error::from(err)),
+        };
+        let file_type = match metadata.mode() {
+            opendal::EntryMode::DIR => FileType::Dir,
+            _ if Filesystem::is_symlink(&metadata) => FileType::Symlink,
+            _ => FileType::File,
+        };
+        let stored_mode = metadata
+            .user_metadata()
+            .and_then(|m| m.get(MODE_METADATA_KEY))
+            .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+            .or_else(|| self.local_mode(path));
+        let mut attr = OpenedFile::new(file_type, path);
+        attr.compression = metadata
+            .user_metadata()
+            .and_then(|m| m.get(COMPRESSION_METADATA_KEY))
+            .and_then(|name| name.parse().ok());
+        attr.set_size(Filesystem::object_size(&metadata));
+        if let Some(mode) = stored_mode {
+            attr.set_permissions(mode);
+        }
+        let stored_owner = metadata.user_metadata().and_then(|m| {
+            let uid = m.get(UID_METADATA_KEY)?.parse().ok()?;
+            let gid = m.get(GID_METADATA_KEY)?.parse().ok()?;
+            Some((uid, gid))
+        });
+        attr.owner = stored_owner;
+        let modified = metadata.last_modified().map(|modified| {
+            Duration::new(
+                modified.timestamp().max(0) as u64,
+                modified.timestamp_subsec_nanos(),
+            )
+        });
+        if let Some(modified) = modified {
+            attr.set_times(modified);
+        }
+        if metadata.mode() == opendal::EntryMode::DIR && self.count_subdirs {
+            // a failed listing only costs the accurate count, not the whole stat.
+            let subdirs = self.do_count_subdirs(path).await.unwrap_or(0);
+            attr.metadata.nlink = DEFAULT_DIR_NLINK + subdirs;
+        }
+        if lookup {
+            attr.nlookup = 1;
+        }
+        let mut opened_files_map = self.opened_files_map.lock().unwrap();
+        if let Some(inode) = opened_files_map.get(path) {
+            attr.metadata.ino = *inode;
+            // count the lookup while holding the map so a concurrent forget can't free the inode.
+            if let Some(file) = self.opened_file(*inode) {
+                let mut file = file.write().unwrap();
+                file.nlookup += attr.nlookup;
+                file.recently_used = true;
+                attr.generation = file.generation;
+                file.set_size(attr.metadata.size);
+                file.compression = attr.compression;
+                // an inode allocated from a listing can't tell symlinks from files.
+                if file.metadata.mode & libc::S_IFMT != attr.metadata.mode & libc::S_IFMT {
+                    file.metadata.mode = attr.metadata.mode;
+                    file.metadata.nlink = attr.metadata.nlink;
+                }
+                // backends without user metadata only know the mode through the cached inode.
+                if stored_mode.is_none() {
+                    attr.set_permissions(file.metadata.mode);
+                }
+                if stored_owner.is_none() {
+                    attr.owner = file.owner;
+                }
+                if modified.is_none() {
+                    attr.metadata.atime = file.metadata.atime;
+                    attr.metadata.mtime = file.metadata.mtime;
+                    attr.metadata.ctime = file.metadata.ctime;
+                    attr.metadata.atimensec = file.metadata.atimensec;
+                    attr.metadata.mtimensec = file.metadata.mtimensec;
+                    attr.metadata.ctimensec = file.metadata.ctimensec;
+                }
+            }
+        } else {
+            self.insert_inode(&mut opened_files_map, &mut attr);
+        }
+
+        Ok(attr)
+    }
+
+    /// Looks up the entry of `parent_path` whose name matches `name` ignoring case. The inode
+    /// keeps the stored name, so later requests on it use that casing.
+    async fn do_lookup_ignore_case(&self, parent_path: &str, name: &str) -> Result<OpenedFile> {
+        match self.do_find_name_ignore_case(parent_path, name).await? {
+            Some(stored_name) => {
+                let path = Filesystem::join_path(parent_path, &stored_name);
+                self.do_get_metadata(&path, true).await
+            }
+            None => Err(Error::from(libc::ENOENT)),
+        }
+    }
+
+    /// Path of the entry a name removes or renames, the name itself unless names are looked
+    /// up ignoring case and only another casing is stored.
+    async fn do_resolve_name(&self, parent_path: &str, name: &str) -> String {
+        let path = Filesystem::join_path(parent_path, name);
+        if !self.case_insensitive {
+            return path;
+        }
+        let known = self.opened_files_map.lock().unwrap().contains_key(&path);
+        if known || self.do_get_entry_mode(&path).await.is_some() {
+            return path;
+        }
+        match self.do_find_name_ignore_case(parent_path, name).await {
+            Ok(Some(stored_name)) => Filesystem::join_path(parent_path, &stored_name),
+            _ => path,
+        }
+    }
+
+    async fn do_find_name_ignore_case(
+        &self,
+        parent_path: &str,
+        name: &str,
+    ) -> Result<Option<String>> {
+        let lowercase_name = name.to_lowercase();
+        let cached_listing = self.dir_cache.lock().unwrap().get(parent_path);
+        let stored_name = match cached_listing {
+            Some(entries) => entries
+                .iter()
+                .find(|entry| entry.name.to_lowercase() == lowercase_name)
+                .map(|entry| entry.name.clone()),
+            None => {
+                let mut stream = self.do_open_dir_stream(parent_path).await?;
+                let mut stored_name = None;
+                while let Some(entry) = self.do_next_dir_entry(&mut stream).await? {
+                    if entry.name.to_lowercase() == lowercase_name {
+                        stored_name = Some(entry.name);
+                        break;
+                    }
+                }
+                stored_name
+            }
+        };
+
+        Ok(stored_name)
+    }
+
+    async fn do_count_subdirs(&self, path: &str) -> Result<u32> {
+        let path = if !path.ends_with('/') {
+            format!("{}/", path)
+        } else {
+            path.to_string()
+        };
+        let entries = self.core().list(&path).await.map_err(Error::from)?;
+        // some services list the directory itself as well.
+        let subdirs = entries
+            .iter()
+            .filter(|entry| entry.metadata().mode() == opendal::EntryMode::DIR)
+            .filter(|entry| entry.path().trim_start_matches('/') != path.trim_start_matches('/'))
+            .count();
+
+        Ok(subdirs as u32)
+    }
+
+    async fn do_set_writer(
+        &self,
+        fh: u64,
+        path: &str,
+        flags: u32,
+        metadata: Vec<(String, String)>,
+    ) -> Result<bool> {
+        let (is_write, is_append, is_trunc) = self.check_flags(flags)?;
+        if !is_write {
+            return Ok(false);
+        }
+        self.invalidate_read_cache(path);
+
+        let is_trunc = is_trunc && !is_append;
+        if is_trunc {
+            self.core()
+                .write(path, Buffer::new())
+                .await
+                .map_err(Error::from)?;
+            self.set_stored_compression(path, None);
+        }
+
+        let compressed = self.write_compression().is_some();
+        let (writer, pending) = if compressed {
+            // the object is compressed as a whole, so an append starts from its contents.
+            let pending = if is_append {
+                self.do_read_object(path).await?.to_vec()
+            } else {
+                Vec::new()
+            };
+            (None, pending)
+        } else {
+            let mut writer = self.core().writer_with(path).append(is_append);
+            // appends keep the metadata the object was created with.
+            if !is_append
+                && self
+                    .core()
+                    .info()
+                    .full_capability()
+                    .write_with_user_metadata
+            {
+                writer = writer.user_metadata(metadata);
+            }
+            if let Some(content_type) = self.content_type(path) {
+                writer = writer.content_type(content_type);
+            }
+            (Some(writer.await.map_err(Error::from)?), Vec::new())
+        };
+        let written = if is_append && !compressed {
+            self.core()
+                .stat(path)
+                .await
+                .map_err(Error::from)?
+                .content_length()
+        } else {
+            pending.len() as u64
+        };
+
+        let inner_writer = InnerWriter {
+            path: path.to_string(),
+            writer,
+            written,
+            pending,
+            // created and truncated objects must exist even when nothing is written.
+            dirty: is_trunc || flags & libc::O_CREAT as u32 != 0,
+            append: is_append,
+            appends_object: is_append && !compressed,
+            // an appended object also holds what was there before, so only replaced ones
+            // are checked.
+            // compressed files are written by do_write_object, which doesn't check them.
+            digest: (self.verify_writes && !is_append && !compressed).then(Md5::new),
+        };
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        opened_file_writer.insert(fh, inner_writer);
+
+        Ok(is_trunc)
+    }
+
+    /// Opens a new writer for `fh` holding the rebuilt contents of an object, which are
+    /// written once it is flushed or closed.
+    async fn do_reopen_writer(&self, fh: u64, path: &str, content: Vec<u8>) -> Result<()> {
+        let compressed = self.write_compression().is_some();
+        let writer = if compressed {
+            None
+        } else {
+            let mut writer = self.core().writer_with(path);
+            if self
+                .core()
+                .info()
+                .full_capability()
+                .write_with_user_metadata
+            {
+                if let Some(metadata) = self.cached_object_metadata(path) {
+                    writer = writer.user_metadata(metadata);
+                }
+            }
+            if let Some(content_type) = self.content_type(path) {
+                writer = writer.content_type(content_type);
+            }
+            Some(writer.await.map_err(Error::from)?)
+        };
+        let inner_writer = InnerWriter {
+            path: path.to_string(),
+            writer,
+            written: content.len() as u64,
+            pending: content,
+            dirty: true,
+            append: false,
+            appends_object: false,
+            digest: (self.verify_writes && !compressed).then(Md5::new),
+        };
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        opened_file_writer.insert(fh, inner_writer);
+
+        Ok(())
+    }
+
+    async fn do_release_writer(&self, fh: u64) -> Result<()> {
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        // the handle is gone, so a writer that failed to close is dropped with it rather than
+        // closed again later.
+        if let Some(mut inner_writer) = opened_file_writer.remove(&fh) {
+            self.do_close_writer(&mut inner_writer).await?;
+            self.invalidate_parent_dir_cache(&inner_writer.path);
+        }
+
+        Ok(())
+    }
+
+    /// Commits what `fh` wrote so far. On backends that can append, a new writer resumes at
+    /// the end of the committed object, otherwise the next write ending the file reopens one
+    /// with its contents.
+    async fn do_sync_writer(&self, fh: u64) -> Result<()> {
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        let Some(mut inner_writer) = opened_file_writer.remove(&fh) else {
+            return Ok(());
+        };
+        self.do_close_writer(&mut inner_writer).await?;
+        self.invalidate_parent_dir_cache(&inner_writer.path);
+        if inner_writer.writer.is_none() || !self.core().info().full_capability().write_can_append {
+            return Ok(());
+        }
+
+        let writer = self
+            .core()
+            .writer_with(&inner_writer.path)
+            .append(true)
+            .await
+            .map_err(Error::from)?;
+        opened_file_writer.insert(
+            fh,
+            InnerWriter {
+                path: inner_writer.path,
+                writer: Some(writer),
+                written: inner_writer.written,
+                pending: Vec::new(),
+                dirty: false,
+                append: inner_writer.append,
+                appends_object: true,
+                // only replaced objects are checked, this one keeps what was committed.
+                digest: None,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn do_close_writer(&self, inner_writer: &mut InnerWriter) -> Result<()> {
+        let dirty = inner_writer.dirty;
+        inner_writer.close().await.map_err(Error::from)?;
+        if inner_writer.writer.is_none() {
+            if !dirty {
+                return Ok(());
+            }
+            // a failed close must not write an emptied buffer on the next attempt.
+            inner_writer.dirty = false;
+            let data = std::mem::take(&mut inner_writer.pending);
+            return self.do_write_object(&inner_writer.path, data).await;
+        }
+        match inner_writer.digest.take() {
+            Some(digest) if dirty => self.do_verify_object(&inner_writer.path, digest).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Compares an object with the hash of what was written, through its ETag when the
+    /// backend reports the MD5 there and by reading it back otherwise.
+    async fn do_verify_object(&self, path: &str, digest: Md5) -> Result<()> {
+        let expected = format!("{:x}", digest.finalize());
+        let metadata = self.core().stat(path).await.map_err(Error::from)?;
+        if metadata
+            .etag()
+            .is_some_and(|etag| etag.trim_matches('"') == expected)
+        {
+            return Ok(());
+        }
+
+        let data = self.core().read(path).await.map_err(Error::from)?;
+        let mut stored = Md5::new();
+        for chunk in data {
+            stored.update(&chunk);
+        }
+        let stored = format!("{:x}", stored.finalize());
+        if stored != expected {
+            warn!(
+                "written object {} doesn't match, md5 is {} instead of {}",
+                path, stored, expected
+            );
+            return Err(Error::from(libc::EIO));
+        }
+
+        Ok(())
+    }
+
+    async fn do_flush_writer(&self, fh: u64) -> Result<()> {
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        if let Some(inner_writer) = opened_file_writer.get_mut(&fh) {
+            inner_writer.flush().await.map_err(Error::from)?;
+        }
+
+        Ok(())
+    }
+
+    async fn do_release_path_writers(&self, path: &str) -> Result<()> {
+        self.do_release_writers_matching(|p| p == path).await
+    }
+
+    async fn do_release_dir_writers(&self, path: &str) -> Result<()> {
+        if path == "/" {
+            return self.do_release_writers_matching(|_| true).await;
+        }
+        let prefix = format!("{}/", path);
+        self.do_release_writers_matching(|p| p.starts_with(&prefix))
+            .await
+    }
+
+    async fn do_release_writers_matching(&self, matches: impl Fn(&str) -> bool) -> Result<()> {
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        let handles: Vec<u64> = opened_file_writer
+            .iter()
+            .filter(|(_, inner_writer)| matches(&inner_writer.path))
+            .map(|(fh, _)| *fh)
+            .collect();
+        for fh in handles {
+            if let Some(mut inner_writer) = opened_file_writer.remove(&fh) {
+                self.do_close_writer(&mut inner_writer).await?;
+                self.invalidate_parent_dir_cache(&inner_writer.path);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn do_close_writers(&self) -> Result<usize> {
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        let mut result = Ok(opened_file_writer.len());
+        // keep closing the remaining writers so one failure doesn't lose every other file.
+        for (_, mut inner_writer) in opened_file_writer.drain() {
+            if let Err(err) = self.do_close_writer(&mut inner_writer).await {
+                warn!("failed to close writer of {}: {}", inner_writer.path, err);
+                result = Err(err);
+            }
+        }
+
+        result
+    }
+
+    /// Sets the size of an object, through the writer of `fh` when the handle truncating it
+    /// is the one writing it.
+    async fn do_truncate(&self, fh: Option<u64>, path: &str, size: u64) -> Result<()> {
+        self.invalidate_read_cache(path);
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        let mut writers = opened_file_writer
+            .iter_mut()
+            .filter(|(_, inner_writer)| inner_writer.path == path);
+        // a single streaming writer already replaces the object with what it has written, and
+        // its own handle extends that with zeros.
+        if let (Some((writer_fh, inner_writer)), None) = (writers.next(), writers.next()) {
+            if inner_writer.written == size {
+                inner_writer.dirty = true;
+                return Ok(());
+            }
+            if fh == Some(*writer_fh) && size > inner_writer.written {
+                // the zeros are held until the writer is flushed.
+                self.check_file_size(size)?;
+                let len = inner_writer.pending.len() + (size - inner_writer.written) as usize;
+                inner_writer.pending.resize(len, 0);
+                inner_writer.written = size;
+                inner_writer.dirty = true;
+                return Ok(());
+            }
+        }
+        drop(opened_file_writer);
+        // the object is rebuilt in memory at its new size.
+        self.check_file_size(size)?;
+        self.do_release_path_writers(path).await?;
+
+        let mut data = Vec::new();
+        if size > 0 {
+            let len = self
+                .core()
+                .stat(path)
+                .await
+                .map_err(Error::from)?
+                .content_length();
+            if len > 0 {
+                data = self.do_read_range(path, 0..size).await?.to_vec();
+            }
+            data.resize(size as usize, 0);
+        }
+        self.do_write_object(path, data).await?;
+
+        Ok(())
+    }
+
+    /// Replaces the whole object, keeping the mode and owner known for it.
+    async fn do_write_object(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        let core = self.core();
+        let mut metadata = if core.info().full_capability().write_with_user_metadata {
+            self.cached_object_metadata(path)
+        } else {
+            None
+        };
+        let compression = self.write_compression();
+        let data = match compression {
+            Some(compression) => {
+                metadata.get_or_insert_with(Vec::new).extend([
+                    (
+                        COMPRESSION_METADATA_KEY.to_string(),
+                        compression.name().to_string(),
+                    ),
+                    (SIZE_METADATA_KEY.to_string(), data.len().to_string()),
+                ]);
+                compression.compress(&data).map_err(|err| {
+                    warn!("failed to compress {}: {}", path, err);
+                    Error::from(libc::EIO)
+                })?
+            }
+            None => data,
+        };
+        let mut write = core.write_with(path, data);
+        if let Some(metadata) = metadata {
+            write = write.user_metadata(metadata);
+        }
+        if let Some(content_type) = self.content_type(path) {
+            write = write.content_type(content_type);
+        }
+        write.await.map_err(Error::from)?;
+        self.set_stored_compression(path, compression);
+
+        Ok(())
+    }
+
+    /// Object stores can't update user metadata in place, so the object is written again.
+    async fn do_persist_metadata(&self, path: &str) -> Result<()> {
+        self.do_release_path_writers(path).await?;
+        self.invalidate_read_cache(path);
+        let data = self.do_read_object(path).await?;
+        self.do_write_object(path, data.to_vec()).await
+    }
+
+    /// Reads a whole object, decompressing it when it is stored compressed.
+    async fn do_read_object(&self, path: &str) -> Result<Buffer> {
+        let data = self.core().read(path).await.map_err(Error::from)?;
+        self.decompress_object(path, data)
+    }
+
+    /// Writes out pending data of the path and returns its whole contents.
+    async fn do_orphan_contents(&self, path: &str) -> Result<Vec<u8>> {
+        self.do_release_path_writers(path).await?;
+        Ok(self.do_read_object(path).await?.to_vec())
+    }
+
+    async fn do_punch_hole(
+        &self,
+        fh: u64,
+        path: &str,
+        offset: u64,
+        length: u64,
+        size: u64,
+    ) -> Result<()> {
+        if offset >= size {
+            return Ok(());
+        }
+        // the zeros are written like any other data, so the hole is bounded like a rewrite.
+        self.check_file_size(size)?;
+        let len = min(length, size - offset) as usize;
+        self.do_write(fh, path, offset, Buffer::from(vec![0; len]))
+            .await?;
+
+        Ok(())
+    }
+
+    async fn do_delete(&self, path: &str) -> Result<()> {
+        self.invalidate_read_cache(path);
+        self.core().delete(path).await.map_err(Error::from)?;
+
+        Ok(())
+    }
+
+    /// Closes writers at or below both paths of a rename, so the moved object holds all data
+    /// written before it and no writer later recreates a path that changed hands.
+    async fn do_release_rename_writers(&self, old_path: &str, new_path: &str) -> Result<()> {
+        for path in [old_path, new_path] {
+            self.do_release_path_writers(path).await?;
+            self.do_release_dir_writers(path).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn do_rename(&self, old_path: &str, new_path: &str) -> Result<()> {
+        self.invalidate_read_cache(old_path);
+        self.invalidate_read_cache(new_path);
+        let capability = self.core().info().full_capability();
+        if capability.rename {
+            self.core()
+                .rename(old_path, new_path)
+                .await
+                .map_err(Error::from)?;
+        } else {
+            self.core()
+                .copy(old_path, new_path)
+                .await
+                .map_err(Error::from)?;
+            self.core().delete(old_path).await.map_err(Error::from)?;
+        }
+
+        Ok(())
+    }
+
+    /// OpenDAL cannot swap two paths atomically, so the swap goes through a temporary path
+    /// and the guest may briefly see either name missing.
+    ///
+    /// A failed move puts back the ones made before it, so both objects keep their names.
+    async fn do_exchange(&self, old_path: &str, new_path: &str, tmp_path: &str) -> Result<()> {
+        self.do_rename(old_path, tmp_path).await?;
+        if let Err(err) = self.do_rename(new_path, old_path).await {
+            self.do_undo_rename(tmp_path, old_path).await;
+            return Err(err);
+        }
+        if let Err(err) = self.do_rename(tmp_path, new_path).await {
+            self.do_undo_rename(old_path, new_path).await;
+            self.do_undo_rename(tmp_path, old_path).await;
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    async fn do_undo_rename(&self, from: &str, to: &str) {
+        if let Err(err) = self.do_rename(from, to).await {
+            warn!("failed to move {} back to {}: {}", from, to, err);
+        }
+    }
+
+    /// Symlinks are persisted as objects holding the target path, so only targets
+    /// inside the same backend resolve once the guest follows them.
+    async fn do_symlink(&self, path: &str, target: &str) -> Result<()> {
+        self.invalidate_read_cache(path);
+        self.core()
+            .write_with(path, target.to_string())
+            .user_metadata([(SYMLINK_METADATA_KEY.to_string(), "true".to_string())])
+            .await
+            .map_err(Error::from)?;
+
+        Ok(())
+    }
+
+    /// Object stores have no hard links, so the new name is an independent copy of the
+    /// object with its own inode and nlink of 1, later writes to one are not seen by the other.
+    async fn do_link(&self, old_path: &str, new_path: &str) -> Result<()> {
+        self.invalidate_read_cache(new_path);
+        if self.core().info().full_capability().copy {
+            self.core()
+                .copy(old_path, new_path)
+                .await
+                .map_err(Error::from)?;
+        } else {
+            let data = self.do_read_object(old_path).await?;
+            self.do_write_object(new_path, data.to_vec()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// ETag of the committed object, data still held by a writer is not covered by it.
+    async fn do_get_etag(&self, path: &str) -> Result<Option<String>> {
+        let metadata = self.core().stat(path).await.map_err(Error::from)?;
+
+        Ok(metadata.etag().map(str::to_string))
+    }
+
+    async fn do_readlink(&self, path: &str) -> Result<Vec<u8>> {
+        let metadata = self.core().stat(path).await.map_err(Error::from)?;
+        if !Filesystem::is_symlink(&metadata) {
+            return Err(Error::from(libc::EINVAL));
+        }
+        let data = self.core().read(path).await.map_err(Error::from)?;
+
+        Ok(data.to_vec())
+    }
+
+    async fn do_read(&self, path: &str, offset: u64, size: u32, direct: bool) -> Result<Buffer> {
+        let opened_file_writer = self.opened_files_writer.lock().await;
+        let mut unflushed = false;
+        for inner_writer in opened_file_writer
+            .values()
+            .filter(|inner_writer| inner_writer.path == path)
+        {
+            let pending_start = inner_writer.written - inner_writer.pending.len() as u64;
+            if offset >= pending_start && offset < inner_writer.written {
+                let start = (offset - pending_start) as usize;
+                let end = min(start + size as usize, inner_writer.pending.len());
+                return Ok(Buffer::from(inner_writer.pending[start..end].to_vec()));
+            }
+            unflushed |= offset < inner_writer.written;
+        }
+        drop(opened_file_writer);
+        // the rest of the written data is only visible once the writers are closed.
+        if unflushed {
+            self.do_release_path_writers(path).await?;
+        }
+
+        // direct I/O reads what the backend holds now, past the read-ahead and object caches.
+        if direct {
+            return self.do_read_range(path, offset..offset + size as u64).await;
+        }
+
+        // compressed objects are only read whole, so all of it is kept for later reads.
+        if self.stored_compression(path).is_some() {
+            if let Some(data) = self
+                .read_cache
+                .lock()
+                .unwrap()
+                .get(path, offset, size as u64)
+            {
+                return Ok(data);
+            }
+            let data = self.do_read_object(path).await?;
+            let start = min(offset as usize, data.len());
+            let end = min(start + size as usize, data.len());
+            let out = data.slice(start..end);
+            self.read_cache.lock().unwrap().insert(path, 0, data, true);
+            return Ok(out);
+        }
+
+        if self.read_ahead_size == 0 {
+            return self.do_read_range(path, offset..offset + size as u64).await;
+        }
+
+        if let Some(data) = self
+            .read_cache
+            .lock()
+            .unwrap()
+            .get(path, offset, size as u64)
+        {
+            return Ok(data);
+        }
+
+        // fetch a whole read-ahead window so following sequential reads are served from memory.
+        let window = max(self.read_ahead_size, size as u64);
+        let data = self.do_read_range(path, offset..offset + window).await?;
+        let eof = (data.len() as u64) < window;
+        let out = data.slice(..min(size as usize, data.len()));
+        self.read_cache
+            .lock()
+            .unwrap()
+            .insert(path, offset, data, eof);
+
+        Ok(out)
+    }
+
+    async fn do_setup_mapping(&self, path: &str, offset: u64, len: u64) -> Result<File> {
+        // data still buffered in a writer must be visible through the mapping.
+        self.do_release_path_writers(path).await?;
+        let data = self.do_read_range(path, offset..offset + len).await?;
+
+        let fd = unsafe { libc::memfd_create(c"ovfs-dax".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(Error::from(libc::EIO));
+        }
+        let region = unsafe { File::from_raw_fd(fd) };
+        // the window is mapped in whole, so the part past the end of the object reads as zeros.
+        region.set_len(len).map_err(|_| Error::from(libc::EIO))?;
+        region
+            .write_all_at(&data.to_bytes(), 0)
+            .map_err(|_| Error::from(libc::EIO))?;
+
+        Ok(region)
+    }
+
+    async fn do_read_range(&self, path: &str, range: Range<u64>) -> Result<Buffer> {
+        if self.stored_compression(path).is_some() {
+            let data = self.do_read_object(path).await?;
+            let start = min(range.start, data.len() as u64) as usize;
+            let end = min(range.end, data.len() as u64) as usize;
+            return Ok(data.slice(start..end));
+        }
+        match self.core().read_with(path).range(range).await {
+            Ok(data) => Ok(data),
+            // the object may have shrunk since its size was cached.
+            Err(err) if err.kind() == ErrorKind::RangeNotSatisfied => Ok(Buffer::new()),
+            Err(err) => Err(Error::from(err)),
+        }
+    }
+
+    async fn do_write(&self, fh: u64, path: &str, offset: u64, data: Buffer) -> Result<usize> {
+        self.invalidate_read_cache(path);
+        let len = data.len();
+        // direct I/O writes are passed to the backend writer without being gathered first.
+        let direct = self.handle_direct_io(fh);
+        let mut opened_file_writer = self.opened_files_writer.lock().await;
+        if let Some(inner_writer) = opened_file_writer.get_mut(&fh) {
+            // another appender may have moved the end since this writer was opened.
+            let offset = if inner_writer.append {
+                inner_writer.written
+            } else {
+                offset
+            };
+            // a write may also rewrite data still pending, e.g. the writeback cache sending a
+            // partial page again once it is filled.
+            let pending_start = inner_writer.written - inner_writer.pending.len() as u64;
+            if offset >= pending_start && offset <= inner_writer.written {
+                let start = (offset - pending_start) as usize;
+                let end = start + len;
+                if inner_writer.pending.len() < end {
+                    inner_writer.pending.resize(end, 0);
+                }
+                inner_writer.pending[start..end].copy_from_slice(&data.to_bytes());
+                inner_writer.written = pending_start + inner_writer.pending.len() as u64;
+                inner_writer.dirty = true;
+                if direct || inner_writer.pending.len() >= self.write_buffer_size {
+                    inner_writer.flush().await.map_err(Error::from)?;
+                }
+                return Ok(len);
+            }
+        }
+
+        // the write is not sequential, or the handle's writer was closed e.g. by fsync, so
+        // finalize the streaming writer and fall back to rewriting the whole object with the
+        // data placed at the requested offset.
+        if let Some(mut inner_writer) = opened_file_writer.remove(&fh) {
+            self.do_close_writer(&mut inner_writer).await?;
+        }
+        drop(opened_file_writer);
+        debug!(
+            "write: rewriting {} for fh={} offset={} size={}",
+            path, fh, offset, len
+        );
+
+        let end = offset + len as u64;
+        let size = match self.core().stat(path).await {
+            Ok(metadata) => Filesystem::object_size(&metadata),
+            Err(err) if err.kind() == ErrorKind::NotFound => 0,
+            Err(err) => return Err(Error::from(err)),
+        };
+        self.check_file_size(max(size, end))?;
+
+        let mut content = match self.core().read(path).await {
+            Ok(content) => self.decompress_object(path, content)?.to_vec(),
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(Error::from(err)),
+        };
+        let start = offset as usize;
+        let end = end as usize;
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[start..end].copy_from_slice(&data.to_vec());
+        // a write ending the file is usually followed by ones continuing it, e.g. after a seek
+        // past the end or an fsync, so the handle streams again instead of rewriting the
+        // object each time.
+        if content.len() == end && self.handle_writes(fh) {
+            self.do_reopen_writer(fh, path, content).await?;
+        } else {
+            self.do_write_object(path, content).await?;
+        }
+
+        Ok(len)
+    }
+
+    async fn do_copy_file_range(
+        &self,
+        src_path: &str,
+        off_in: u64,
+        fh_out: u64,
+        dst_path: &str,
+        off_out: u64,
+        len: u64,
+    ) -> Result<u64> {
+        let src_metadata = self.core().stat(src_path).await.map_err(Error::from)?;
+        let src_size = Filesystem::object_size(&src_metadata);
+        if off_in >= src_size {
+            return Ok(0);
+        }
+        let len = min(len, src_size - off_in);
+
+        // copying a whole object into a freshly created or truncated one is done on the backend.
+        if off_in == 0
+            && off_out == 0
+            && len == src_size
+            && len <= u32::MAX as u64
+            && self.core().info().full_capability().copy
+        {
+            let mut opened_file_writer = self.opened_files_writer.lock().await;
+            if opened_file_writer
+                .get(&fh_out)
+                .is_some_and(|inner_writer| inner_writer.written == 0)
+            {
+                if let Some(mut inner_writer) = opened_file_writer.remove(&fh_out) {
+                    self.do_close_writer(&mut inner_writer).await?;
+                }
+                drop(opened_file_writer);
+                self.invalidate_read_cache(dst_path);
+                self.core()
+                    .copy(src_path, dst_path)
+                    .await
+                    .map_err(Error::from)?;
+                self.set_stored_compression(dst_path, self.stored_compression(src_path));
+                return Ok(len);
+            }
+        }
+
+        let len = min(len, self.max_buffer_size as u64);
+        let data = self.do_read_range(src_path, off_in..off_in + len).await?;
+        let copied = self.do_write(fh_out, dst_path, off_out, data).await?;
+
+        Ok(copied as u64)
+    }
+
+    async fn do_get_entry_mode(&self, path: &str) -> Option<opendal::EntryMode> {
+        // object stores only know a directory by its trailing slash.
+        for candidate in [path.to_string(), format!("{}/", path)] {
+            if let Ok(metadata) = self.core().stat(&candidate).await {
+                return Some(metadata.mode());
+            }
+        }
+        None
+    }
 
     async fn do_create_dir(&self, path: &str) -> Result<()> {
         let path = if !path.ends_with('/') {
@@ -853,71 +4001,477 @@ impl Filesystem {
         } else {
             path.to_string()
         };
-        self.core
-            .create_dir(&path)
-            .await
-            .map_err(|err| Error::from(err))?;
+        self.core().create_dir(&path).await.map_err(Error::from)?;
 
         Ok(())
     }
 
-    async fn do_readdir(&self, path: &str) -> Result<Vec<DirEntry>> {
-        let path = if !path.ends_with('/') {
+    /// Stats the entries of a listed page, `prefetch_stats` at a time, so the lookups a guest
+    /// follows a listing with are answered without asking the backend again.
+    async fn do_prefetch_stats(&self, dir_path: &str, page: &[DirEntry]) {
+        let cache_epoch = self.stat_cache.lock().unwrap().epoch();
+        let semaphore = Semaphore::new(self.prefetch_stats);
+        let stats = page.iter().map(|entry| async {
+            let _permit = semaphore.acquire().await.ok()?;
+            let path = Filesystem::join_path(dir_path, &entry.name);
+            // object stores, and the memory service, only know a directory by its trailing slash.
+            let stat_path = if entry.type_ == DEAFULT_DIR_TYPE_IN_DIR_ENTRY {
+                format!("{}/", path)
+            } else {
+                path.clone()
+            };
+            let metadata = self.core().stat(&stat_path).await.ok()?;
+            Some((path, metadata))
+        });
+        let stats = join_all(stats).await;
+
+        let mut stat_cache = self.stat_cache.lock().unwrap();
+        if stat_cache.epoch() != cache_epoch {
+            return;
+        }
+        for (path, metadata) in stats.into_iter().flatten() {
+            stat_cache.insert(&path, metadata);
+        }
+    }
+
+    async fn do_open_dir_stream(&self, path: &str) -> Result<DirStream> {
+        let cache_epoch = self.dir_cache.lock().unwrap().epoch();
+        let list_path = if !path.ends_with('/') {
             format!("{}/", path)
         } else {
             path.to_string()
         };
-
-        let entries = self
-            .core
-            .list(&path)
+        // sizes and times come with the listing, so readdirplus doesn't stat each entry.
+        let lister = self
+            .core()
+            .lister_with(&list_path)
+            .recursive(false)
+            .metakey(Metakey::Mode | Metakey::ContentLength | Metakey::LastModified)
             .await
-            .map_err(|err| Error::from(err))?
-            .into_iter()
-            .enumerate()
-            .map(|(i, entry)| {
-                let metadata = entry.metadata();
-                let file_type = match metadata.mode() {
-                    opendal::EntryMode::DIR => FileType::Dir,
-                    _ => FileType::File,
-                };
-
-                let path = format!("{}/{}", path, entry.name());
-                let mut attr = OpenedFile::new(file_type, &path);
-                attr.metadata.size = metadata.content_length();
-
-                let mut opened_files_map = self.opened_files_map.lock().unwrap();
-                let inode = if let Some(inode) = opened_files_map.get(&path) {
+            .map_err(Error::from)?;
+
+        Ok(DirStream {
+            path: path.to_string(),
+            lister,
+            listed: 0,
+            pending: None,
+            last_name: None,
+            collected: Some(Vec::new()),
+            cache_epoch,
+        })
+    }
+
+    /// Writes the entries from `offset` on that fit into `size` bytes, pulling only as many
+    /// as needed from the backend. Returns the bytes written and the entries they hold.
+    async fn do_fill_dir_page(
+        &self,
+        in_header: &InHeader,
+        plus: bool,
+        stream: &mut DirStream,
+        offset: u64,
+        size: usize,
+        writer: &mut Writer<'_>,
+    ) -> Result<(usize, Vec<DirEntry>)> {
+        let mut total_written = 0;
+        let mut page = Vec::new();
+        while let Some(entry) = self.do_next_dir_entry(stream).await? {
+            if entry.off <= offset {
+                continue;
+            }
+            if total_written + Filesystem::dir_entry_len(&entry, plus) > size {
+                stream.pending = Some(entry);
+                break;
+            }
+            let entry_out = plus.then(|| self.lookup_dir_entry(in_header, &stream.path, &entry));
+            total_written += Filesystem::reply_add_dir_entry(writer, &entry, entry_out)?;
+            page.push(entry);
+        }
+
+        Ok((total_written, page))
+    }
+
+    async fn do_next_dir_entry(&self, stream: &mut DirStream) -> Result<Option<DirEntry>> {
+        if let Some(entry) = stream.pending.take() {
+            return Ok(Some(entry));
+        }
+
+        let list_path = if !stream.path.ends_with('/') {
+            format!("{}/", stream.path)
+        } else {
+            stream.path.clone()
+        };
+        let prefix = list_path.trim_start_matches('/');
+        while let Some(entry) = stream.lister.try_next().await.map_err(Error::from)? {
+            let relative = entry
+                .path()
+                .trim_start_matches('/')
+                .strip_prefix(prefix)
+                .unwrap_or(entry.name())
+                .trim_end_matches('/');
+            // some services list the directory itself as well.
+            if relative.is_empty() {
+                continue;
+            }
+            // flat object stores may return deeper keys, which only imply a directory named
+            // after their first component.
+            let (name, is_dir, size, modified) = match relative.split_once('/') {
+                Some((name, _)) => (name, true, 0, None),
+                None => (
+                    relative,
+                    entry.metadata().mode() == opendal::EntryMode::DIR,
+                    entry.metadata().content_length(),
+                    entry.metadata().last_modified(),
+                ),
+            };
+            if stream.last_name.as_deref() == Some(name) {
+                continue;
+            }
+            stream.last_name = Some(name.to_string());
+            stream.listed += 1;
+
+            let file_type = if is_dir {
+                FileType::Dir
+            } else {
+                FileType::File
+            };
+            let child_path = Filesystem::join_path(&list_path, name);
+            let modified = modified.map(|modified| {
+                Duration::new(
+                    modified.timestamp().max(0) as u64,
+                    modified.timestamp_subsec_nanos(),
+                )
+            });
+            let mut attr = OpenedFile::new(file_type, &child_path);
+            attr.set_size(size);
+            if let Some(modified) = modified {
+                attr.set_times(modified);
+            }
+            let mut opened_files_map = self.opened_files_map.lock().unwrap();
+            let inode = match opened_files_map.get(&child_path) {
+                Some(inode) => {
+                    // like a stat, the listing refreshes what the cached inode knows of a file.
+                    if let Some(file) = self.opened_file(*inode) {
+                        let mut file = file.write().unwrap();
+                        if !is_dir && file.metadata.mode & libc::S_IFMT == libc::S_IFREG {
+                            // a compressed file keeps the size its metadata recorded.
+                            if file.compression.is_none() {
+                                file.set_size(size);
+                            }
+                            if let Some(modified) = modified {
+                                file.set_times(modified);
+                            }
+                        }
+                    }
                     *inode
+                }
+                None => self.insert_inode(&mut opened_files_map, &mut attr),
+            };
+            drop(opened_files_map);
+
+            let dir_entry = DirEntry {
+                ino: inode,
+                off: stream.listed,
+                name: name.to_string(),
+                type_: if is_dir {
+                    DEAFULT_DIR_TYPE_IN_DIR_ENTRY
+                } else {
+                    DEAFULT_FILE_TYPE_IN_DIR_ENTRY
+                },
+            };
+            if let Some(collected) = stream.collected.as_mut() {
+                if collected.len() < DIR_CACHE_MAX_LISTING {
+                    collected.push(dir_entry.clone());
                 } else {
-                    let inode = self
-                        .opened_files
-                        .insert(attr)
-                        .expect("failed to allocate inode");
-                    opened_files_map.insert(path.to_string(), inode as u64);
-                    inode as u64
-                };
-
-                let type_ = match metadata.mode() {
-                    opendal::EntryMode::DIR => DEAFULT_DIR_TYPE_IN_DIR_ENTRY,
-                    _ => DEAFULT_FILE_TYPE_IN_DIR_ENTRY,
-                };
-
-                let mut name = entry.name().to_string();
-                if name.ends_with('/') {
-                    name.truncate(name.len() - 1);
+                    stream.collected = None;
                 }
+            }
+            return Ok(Some(dir_entry));
+        }
 
-                let entry = DirEntry {
-                    ino: inode,
-                    off: i as u64 + 1,
-                    name,
-                    type_,
-                };
-                entry
-            })
-            .collect();
+        // only complete listings are worth caching.
+        if let Some(collected) = stream.collected.take() {
+            let mut dir_cache = self.dir_cache.lock().unwrap();
+            if dir_cache.epoch() == stream.cache_epoch {
+                dir_cache.insert(&stream.path, Arc::new(collected));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use opendal::services::Fs;
+    use opendal::services::Memory;
+
+    use super::*;
+
+    fn filesystem() -> Filesystem {
+        filesystem_with(Operator::new(Memory::default()).unwrap().finish())
+    }
+
+    fn filesystem_with(operator: Operator) -> Filesystem {
+        let config = FilesystemConfig {
+            worker_threads: 1,
+            read_ahead_size: 0,
+            negative_lookup_ttl: Duration::ZERO,
+            dir_cache_ttl: Duration::ZERO,
+            entry_timeout: Duration::from_secs(1),
+            attr_timeout: Duration::from_secs(1),
+            max_buffer_size: 1 << 20,
+            write_buffer_size: 4 << 20,
+            read_only: false,
+            dax: false,
+            writeback: false,
+            verify_writes: false,
+            compression: None,
+            content_types: None,
+            count_subdirs: false,
+            readdirplus: false,
+            case_insensitive: false,
+            uid: 1000,
+            gid: 1000,
+            passthrough_owner: false,
+            max_inodes: 0,
+            max_file_size: 1 << 30,
+            prefetch_stats: 0,
+        };
+        Filesystem::new(operator, config)
+    }
+
+    fn request(opcode: Opcode, nodeid: u64, payload: &[u8]) -> Vec<u8> {
+        let header = InHeader {
+            len: (size_of::<InHeader>() + payload.len()) as u32,
+            opcode: opcode as u32,
+            unique: 1,
+            nodeid,
+            ..Default::default()
+        };
+        [header.as_slice(), payload].concat()
+    }
+
+    // returns the errno of the reply and what follows its header.
+    fn send(fs: &Filesystem, mut request: Vec<u8>) -> (i32, Vec<u8>) {
+        let mut reply = vec![0; 4096];
+        let len = fs
+            .handle_message(
+                Reader::from_bytes(&mut request),
+                Writer::from_bytes(&mut reply),
+            )
+            .unwrap();
+        let mut header = OutHeader::default();
+        header
+            .as_mut_slice()
+            .copy_from_slice(&reply[..size_of::<OutHeader>()]);
+        reply.truncate(len);
+        (-header.error, reply.split_off(size_of::<OutHeader>()))
+    }
+
+    fn init(fs: &Filesystem) {
+        let init = InitIn {
+            major: KERNEL_VERSION,
+            minor: KERNEL_MINOR_VERSION,
+            ..Default::default()
+        };
+        let (errno, _) = send(fs, request(Opcode::Init, 0, init.as_slice()));
+        assert_eq!(errno, 0);
+    }
+
+    #[test]
+    fn test_root_inode_served_from_any_thread() {
+        let fs = filesystem();
+        fs.rt.block_on(fs.core().create_dir("dir/")).unwrap();
+        // slab keys carry the id of the allocating thread, so the root must not land at key 1.
+        let slab = Slab::new();
+        slab.insert(()).unwrap();
+
+        thread::scope(|scope| {
+            scope.spawn(|| init(&fs)).join().unwrap();
+            scope
+                .spawn(|| {
+                    let (errno, out) =
+                        send(&fs, request(Opcode::Getattr, DEFAULT_ROOT_DIR_INODE, &[]));
+                    assert_eq!(errno, 0);
+                    let mut attr = AttrOut::default();
+                    attr.as_mut_slice().copy_from_slice(&out);
+                    assert_eq!(attr.attr.ino, DEFAULT_ROOT_DIR_INODE);
+                    assert_eq!(attr.attr.mode & libc::S_IFMT, libc::S_IFDIR);
+
+                    let (errno, out) = send(
+                        &fs,
+                        request(Opcode::Lookup, DEFAULT_ROOT_DIR_INODE, b"dir\0"),
+                    );
+                    assert_eq!(errno, 0);
+                    let mut entry = EntryOut::default();
+                    entry.as_mut_slice().copy_from_slice(&out);
+                    assert!(entry.nodeid > DEFAULT_ROOT_DIR_INODE);
+
+                    let mkdir = MkdirIn {
+                        mode: 0o755,
+                        umask: 0,
+                    };
+                    let (errno, _) = send(
+                        &fs,
+                        request(
+                            Opcode::Mkdir,
+                            DEFAULT_ROOT_DIR_INODE,
+                            &[mkdir.as_slice(), b"new\0"].concat(),
+                        ),
+                    );
+                    assert_eq!(errno, 0);
+                })
+                .join()
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn test_malformed_requests_decoded_before_dispatch() {
+        let fs = filesystem();
+        init(&fs);
+
+        let write = WriteIn {
+            size: 8,
+            ..Default::default()
+        };
+        let write = [write.as_slice(), b"data"].concat();
+        let (errno, _) = send(&fs, request(Opcode::Write, DEFAULT_ROOT_DIR_INODE, &write));
+        assert_eq!(errno, libc::EIO);
+
+        // names are taken without their NUL.
+        let (errno, _) = send(
+            &fs,
+            request(Opcode::Lookup, DEFAULT_ROOT_DIR_INODE, b"missing"),
+        );
+        assert_eq!(errno, libc::ENOENT);
+    }
+
+    // a directory of its own for each test, served through the fs backend.
+    fn fs_root(name: &str) -> (PathBuf, Filesystem) {
+        let root = std::env::temp_dir().join(format!("ovfs-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let operator = Operator::new(Fs::default().root(root.to_str().unwrap()))
+            .unwrap()
+            .finish();
+        let fs = filesystem_with(operator);
+        init(&fs);
+        (root, fs)
+    }
+
+    #[test]
+    fn test_mkdir_failure_leaves_no_inode() {
+        let (root, fs) = fs_root("mkdir");
+        std::fs::write(root.join("file"), b"").unwrap();
+
+        let (errno, out) = send(
+            &fs,
+            request(Opcode::Lookup, DEFAULT_ROOT_DIR_INODE, b"file\0"),
+        );
+        assert_eq!(errno, 0);
+        let mut entry = EntryOut::default();
+        entry.as_mut_slice().copy_from_slice(&out);
+
+        // a directory below a file can't be created.
+        let mkdir = MkdirIn {
+            mode: 0o755,
+            umask: 0,
+        };
+        let mkdir = [mkdir.as_slice(), b"dir\0"].concat();
+        let (errno, _) = send(&fs, request(Opcode::Mkdir, entry.nodeid, &mkdir));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_ne!(errno, 0);
+        assert_ne!(errno, libc::ENOENT);
+        assert!(!fs.opened_files_map.lock().unwrap().contains_key("file/dir"));
+    }
+
+    // creates a file in the root for writing, returning its inode and handle.
+    fn create(fs: &Filesystem, name: &str) -> (u64, u64) {
+        let create = CreateIn {
+            flags: (libc::O_WRONLY | libc::O_CREAT) as u32,
+            mode: 0o644,
+            ..Default::default()
+        };
+        let create = [create.as_slice(), name.as_bytes(), b"\0"].concat();
+        let (errno, out) = send(fs, request(Opcode::Create, DEFAULT_ROOT_DIR_INODE, &create));
+        assert_eq!(errno, 0);
+        let mut entry = EntryOut::default();
+        entry
+            .as_mut_slice()
+            .copy_from_slice(&out[..size_of::<EntryOut>()]);
+        let mut open = OpenOut::default();
+        open.as_mut_slice()
+            .copy_from_slice(&out[size_of::<EntryOut>()..]);
+        (entry.nodeid, open.fh)
+    }
+
+    fn write(fs: &Filesystem, inode: u64, fh: u64, offset: u64, data: &[u8]) {
+        let write = WriteIn {
+            fh,
+            offset,
+            size: data.len() as u32,
+            ..Default::default()
+        };
+        let write = [write.as_slice(), data].concat();
+        let (errno, _) = send(fs, request(Opcode::Write, inode, &write));
+        assert_eq!(errno, 0);
+    }
+
+    fn fsync(fs: &Filesystem, inode: u64, fh: u64) {
+        let fsync = FsyncIn {
+            fh,
+            ..Default::default()
+        };
+        let (errno, _) = send(fs, request(Opcode::Fsync, inode, fsync.as_slice()));
+        assert_eq!(errno, 0);
+    }
+
+    fn release(fs: &Filesystem, inode: u64, fh: u64) {
+        let release = ReleaseIn {
+            fh,
+            ..Default::default()
+        };
+        let (errno, _) = send(fs, request(Opcode::Release, inode, release.as_slice()));
+        assert_eq!(errno, 0);
+    }
+
+    #[test]
+    fn test_fsync_keeps_streaming() {
+        let (root, fs) = fs_root("fsync");
+        let (inode, fh) = create(&fs, "db");
+        for (i, data) in [b"aaaa", b"bbbb", b"cccc"].into_iter().enumerate() {
+            write(&fs, inode, fh, i as u64 * 4, data);
+            fsync(&fs, inode, fh);
+            let committed = std::fs::read(root.join("db")).unwrap();
+            assert_eq!(committed.len(), (i + 1) * 4);
+            // the handle streams on from the committed end.
+            let writers = fs.rt.block_on(fs.opened_files_writer.lock());
+            assert_eq!(writers.get(&fh).unwrap().written, (i as u64 + 1) * 4);
+        }
+        release(&fs, inode, fh);
+        let content = std::fs::read(root.join("db")).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(content, b"aaaabbbbcccc");
+    }
 
-        Ok(entries)
+    #[test]
+    fn test_fsync_streams_again_without_append() {
+        let fs = filesystem();
+        init(&fs);
+        assert!(!fs.core().info().full_capability().write_can_append);
+        let (inode, fh) = create(&fs, "db");
+        write(&fs, inode, fh, 0, b"aaaa");
+        fsync(&fs, inode, fh);
+        // the write continuing the synced file opens a new writer.
+        write(&fs, inode, fh, 4, b"bbbb");
+        let writers = fs.rt.block_on(fs.opened_files_writer.lock());
+        assert_eq!(writers.get(&fh).unwrap().written, 8);
+        drop(writers);
+        write(&fs, inode, fh, 8, b"cccc");
+        fsync(&fs, inode, fh);
+        release(&fs, inode, fh);
+        let content = fs.rt.block_on(fs.core().read("db")).unwrap();
+        assert_eq!(content.to_vec(), b"aaaabbbbcccc");
     }
 }
@@ -1,7 +1,38 @@
+use std::mem::size_of;
+
 use vm_memory::ByteValued;
 
 use crate::error::*;
 
+pub const FATTR_MODE: u32 = 1 << 0;
+pub const FATTR_UID: u32 = 1 << 1;
+pub const FATTR_GID: u32 = 1 << 2;
+pub const FATTR_SIZE: u32 = 1 << 3;
+pub const FATTR_FH: u32 = 1 << 6;
+pub const FATTR_KILL_SUIDGID: u32 = 1 << 11;
+
+pub const FUSE_BIG_WRITES: u32 = 1 << 5;
+
+pub const FUSE_DO_READDIRPLUS: u32 = 1 << 13;
+
+pub const FUSE_WRITEBACK_CACHE: u32 = 1 << 16;
+
+pub const FUSE_MAX_PAGES: u32 = 1 << 22;
+
+pub const FUSE_MAP_ALIGNMENT: u32 = 1 << 26;
+
+pub const FUSE_HANDLE_KILLPRIV_V2: u32 = 1 << 28;
+
+pub const FUSE_SETUPMAPPING_FLAG_WRITE: u64 = 1 << 0;
+
+pub const FUSE_WRITE_KILL_SUIDGID: u32 = 1 << 2;
+
+pub const FOPEN_DIRECT_IO: u32 = 1 << 0;
+
+pub const FUSE_IOCTL_UNRESTRICTED: u32 = 1 << 1;
+
+pub const FUSE_IOCTL_RETRY: u32 = 1 << 2;
+
 #[non_exhaustive]
 #[derive(Debug)]
 pub enum Opcode {
@@ -9,21 +40,40 @@ pub enum Opcode {
     Forget = 2,
     Getattr = 3,
     Setattr = 4,
+    Readlink = 5,
+    Symlink = 6,
+    Link = 7,
     Mkdir = 9,
     Unlink = 10,
     Rmdir = 11,
+    Rename = 12,
     Open = 14,
     Read = 15,
     Write = 16,
+    Statfs = 17,
     Release = 18,
+    Fsync = 20,
+    Getxattr = 22,
     Flush = 25,
     Init = 26,
     Opendir = 27,
     Readdir = 28,
     Releasedir = 29,
     Fsyncdir = 30,
+    Access = 34,
     Create = 35,
+    Interrupt = 36,
     Destroy = 38,
+    Ioctl = 39,
+    Poll = 40,
+    BatchForget = 42,
+    Fallocate = 43,
+    Readdirplus = 44,
+    Rename2 = 45,
+    Lseek = 46,
+    CopyFileRange = 47,
+    SetupMapping = 48,
+    RemoveMapping = 49,
 }
 
 impl TryFrom<u32> for Opcode {
@@ -35,26 +85,161 @@ impl TryFrom<u32> for Opcode {
             2 => Ok(Opcode::Forget),
             3 => Ok(Opcode::Getattr),
             4 => Ok(Opcode::Setattr),
+            5 => Ok(Opcode::Readlink),
+            6 => Ok(Opcode::Symlink),
+            7 => Ok(Opcode::Link),
             9 => Ok(Opcode::Mkdir),
             10 => Ok(Opcode::Unlink),
             11 => Ok(Opcode::Rmdir),
+            12 => Ok(Opcode::Rename),
             14 => Ok(Opcode::Open),
             15 => Ok(Opcode::Read),
             16 => Ok(Opcode::Write),
+            17 => Ok(Opcode::Statfs),
             18 => Ok(Opcode::Release),
+            20 => Ok(Opcode::Fsync),
+            22 => Ok(Opcode::Getxattr),
             25 => Ok(Opcode::Flush),
             26 => Ok(Opcode::Init),
             27 => Ok(Opcode::Opendir),
             28 => Ok(Opcode::Readdir),
             29 => Ok(Opcode::Releasedir),
             30 => Ok(Opcode::Fsyncdir),
+            34 => Ok(Opcode::Access),
             35 => Ok(Opcode::Create),
+            36 => Ok(Opcode::Interrupt),
             38 => Ok(Opcode::Destroy),
+            39 => Ok(Opcode::Ioctl),
+            40 => Ok(Opcode::Poll),
+            42 => Ok(Opcode::BatchForget),
+            43 => Ok(Opcode::Fallocate),
+            44 => Ok(Opcode::Readdirplus),
+            45 => Ok(Opcode::Rename2),
+            46 => Ok(Opcode::Lseek),
+            47 => Ok(Opcode::CopyFileRange),
+            48 => Ok(Opcode::SetupMapping),
+            49 => Ok(Opcode::RemoveMapping),
             _ => Err(new_vhost_user_fs_error("failed to decode opcode", None)),
         }
     }
 }
 
+impl Opcode {
+    /// Size of the fixed part of the payload read by the handler, names and data follow it.
+    pub fn payload_size(&self) -> usize {
+        match self {
+            Opcode::Forget => size_of::<ForgetIn>(),
+            Opcode::Setattr => size_of::<SetattrIn>(),
+            Opcode::Link => size_of::<LinkIn>(),
+            Opcode::Mkdir => size_of::<MkdirIn>(),
+            Opcode::Rename => size_of::<RenameIn>(),
+            Opcode::Open | Opcode::Opendir => size_of::<OpenIn>(),
+            Opcode::Read | Opcode::Readdir | Opcode::Readdirplus => size_of::<ReadIn>(),
+            Opcode::Write => size_of::<WriteIn>(),
+            Opcode::Release | Opcode::Releasedir => size_of::<ReleaseIn>(),
+            Opcode::Fsync => size_of::<FsyncIn>(),
+            Opcode::Getxattr => size_of::<GetxattrIn>(),
+            Opcode::Flush => size_of::<FlushIn>(),
+            Opcode::Init => size_of::<InitIn>(),
+            Opcode::Access => size_of::<AccessIn>(),
+            Opcode::Create => size_of::<CreateIn>(),
+            Opcode::Interrupt => size_of::<InterruptIn>(),
+            Opcode::Ioctl => size_of::<IoctlIn>(),
+            Opcode::Poll => size_of::<PollIn>(),
+            Opcode::BatchForget => size_of::<BatchForgetIn>(),
+            Opcode::Fallocate => size_of::<FallocateIn>(),
+            Opcode::Rename2 => size_of::<Rename2In>(),
+            Opcode::Lseek => size_of::<LseekIn>(),
+            Opcode::CopyFileRange => size_of::<CopyFileRangeIn>(),
+            Opcode::SetupMapping => size_of::<SetupMappingIn>(),
+            Opcode::RemoveMapping => size_of::<RemoveMappingIn>(),
+            Opcode::Lookup
+            | Opcode::Getattr
+            | Opcode::Readlink
+            | Opcode::Symlink
+            | Opcode::Unlink
+            | Opcode::Rmdir
+            | Opcode::Statfs
+            | Opcode::Fsyncdir
+            | Opcode::Destroy => 0,
+        }
+    }
+
+    /// Whether the guest waits for an answer to the request.
+    pub fn has_reply(&self) -> bool {
+        !matches!(
+            self,
+            Opcode::Forget | Opcode::BatchForget | Opcode::Interrupt
+        )
+    }
+}
+
+/// A request whose header and payload were checked against the bytes received for it.
+#[derive(Debug)]
+pub struct DecodedRequest<'a> {
+    pub header: InHeader,
+    pub opcode: Opcode,
+    /// Everything following the header, starting with the opcode's fixed payload.
+    pub payload: &'a [u8],
+}
+
+// unknown opcodes fail with ENOSYS, and a length too short for the header and the opcode's
+// fixed payload with EINVAL. a length other than the bytes received fails with EIO.
+fn check_request(header: &InHeader, received: usize) -> Result<Opcode> {
+    let claimed = (header.len as usize)
+        .checked_sub(size_of::<InHeader>())
+        .ok_or(Error::from(libc::EINVAL))?;
+    let opcode = Opcode::try_from(header.opcode).map_err(|_| Error::from(libc::ENOSYS))?;
+    if claimed < opcode.payload_size() {
+        return Err(Error::from(libc::EINVAL));
+    }
+    if claimed != received {
+        return Err(Error::from(libc::EIO));
+    }
+    Ok(opcode)
+}
+
+/// Decodes a whole request held in `buf`, checking every length it carries before anything
+/// is read past the fixed payload. Every request the guest sends is decoded with it before
+/// it is handled.
+///
+/// Unknown opcodes fail with ENOSYS, lengths too short for the opcode with EINVAL and other
+/// length mismatches with EIO. Names are not checked, handlers take one without its NUL.
+///
+/// Needs no queue, so malformed guest input can be fed to it directly, e.g. by a fuzzer.
+pub fn decode_request(buf: &[u8]) -> Result<DecodedRequest<'_>> {
+    let header: InHeader = read_obj(buf).ok_or(Error::from(libc::EIO))?;
+    let payload = &buf[size_of::<InHeader>()..];
+    let opcode = check_request(&header, payload.len())?;
+
+    let rest = &payload[opcode.payload_size()..];
+    let trailing = match opcode {
+        Opcode::Write => read_obj::<WriteIn>(payload).map(|write| write.size as usize),
+        Opcode::BatchForget => read_obj::<BatchForgetIn>(payload)
+            .and_then(|forget| (forget.count as usize).checked_mul(size_of::<ForgetOne>())),
+        Opcode::RemoveMapping => read_obj::<RemoveMappingIn>(payload)
+            .and_then(|remove| (remove.count as usize).checked_mul(size_of::<RemoveMappingOne>())),
+        _ => Some(0),
+    };
+    if trailing.is_none_or(|trailing| trailing > rest.len()) {
+        return Err(Error::from(libc::EIO));
+    }
+
+    Ok(DecodedRequest {
+        header,
+        opcode,
+        payload,
+    })
+}
+
+/// Copies an object from the start of `buf`, which needn't be aligned for it.
+pub(crate) fn read_obj<T: ByteValued + Default>(buf: &[u8]) -> Option<T> {
+    let mut obj = T::default();
+    obj.as_mut_slice()
+        .copy_from_slice(buf.get(..size_of::<T>())?);
+    Some(obj)
+}
+
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Attr {
@@ -76,6 +261,21 @@ pub struct Attr {
     pub flags: u32,
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Kstatfs {
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub bsize: u32,
+    pub namelen: u32,
+    pub frsize: u32,
+    pub padding: u32,
+    pub spare: [u32; 6],
+}
+
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct InHeader {
@@ -124,6 +324,26 @@ pub struct InitOut {
     pub unused: [u32; 7],
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ForgetIn {
+    pub nlookup: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ForgetOne {
+    pub nodeid: u64,
+    pub nlookup: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BatchForgetIn {
+    pub count: u32,
+    pub dummy: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct AttrOut {
@@ -145,6 +365,12 @@ pub struct EntryOut {
     pub attr: Attr,
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatfsOut {
+    pub st: Kstatfs,
+}
+
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct DirEntryOut {
@@ -154,6 +380,34 @@ pub struct DirEntryOut {
     pub type_: u32,
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DirEntryPlusOut {
+    pub entry_out: EntryOut,
+    pub dirent: DirEntryOut,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SetattrIn {
+    pub valid: u32,
+    pub padding: u32,
+    pub fh: u64,
+    pub size: u64,
+    pub lock_owner: u64,
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub atimensec: u32,
+    pub mtimensec: u32,
+    pub ctimensec: u32,
+    pub mode: u32,
+    pub unused4: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub unused5: u32,
+}
+
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct CreateIn {
@@ -170,6 +424,26 @@ pub struct MkdirIn {
     pub umask: u32,
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RenameIn {
+    pub newdir: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rename2In {
+    pub newdir: u64,
+    pub flags: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LinkIn {
+    pub oldnodeid: u64,
+}
+
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct OpenIn {
@@ -204,6 +478,162 @@ pub struct WriteOut {
     pub padding: u32,
 }
 
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReleaseIn {
+    pub fh: u64,
+    pub flags: u32,
+    pub release_flags: u32,
+    pub lock_owner: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FlushIn {
+    pub fh: u64,
+    pub unused: u32,
+    pub padding: u32,
+    pub lock_owner: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FsyncIn {
+    pub fh: u64,
+    pub fsync_flags: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InterruptIn {
+    pub unique: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AccessIn {
+    pub mask: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoctlIn {
+    pub fh: u64,
+    pub flags: u32,
+    pub cmd: u32,
+    pub arg: u64,
+    pub in_size: u32,
+    pub out_size: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoctlIovec {
+    pub base: u64,
+    pub len: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoctlOut {
+    pub result: i32,
+    pub flags: u32,
+    pub in_iovs: u32,
+    pub out_iovs: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PollIn {
+    pub fh: u64,
+    pub kh: u64,
+    pub flags: u32,
+    pub events: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PollOut {
+    pub revents: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GetxattrIn {
+    pub size: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GetxattrOut {
+    pub size: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FallocateIn {
+    pub fh: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub mode: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LseekIn {
+    pub fh: u64,
+    pub offset: u64,
+    pub whence: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LseekOut {
+    pub offset: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CopyFileRangeIn {
+    pub fh_in: u64,
+    pub off_in: u64,
+    pub nodeid_out: u64,
+    pub fh_out: u64,
+    pub off_out: u64,
+    pub len: u64,
+    pub flags: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SetupMappingIn {
+    pub fh: u64,
+    pub foffset: u64,
+    pub len: u64,
+    pub flags: u64,
+    pub moffset: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RemoveMappingIn {
+    pub count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RemoveMappingOne {
+    pub moffset: u64,
+    pub len: u64,
+}
+
 #[repr(C)]
 #[derive(Debug, Default, Clone, Copy)]
 pub struct ReadIn {
@@ -220,13 +650,153 @@ unsafe impl ByteValued for InHeader {}
 unsafe impl ByteValued for OutHeader {}
 unsafe impl ByteValued for InitIn {}
 unsafe impl ByteValued for InitOut {}
+unsafe impl ByteValued for ForgetIn {}
+unsafe impl ByteValued for ForgetOne {}
+unsafe impl ByteValued for BatchForgetIn {}
 unsafe impl ByteValued for AttrOut {}
 unsafe impl ByteValued for EntryOut {}
 unsafe impl ByteValued for DirEntryOut {}
+unsafe impl ByteValued for DirEntryPlusOut {}
+unsafe impl ByteValued for StatfsOut {}
+unsafe impl ByteValued for SetattrIn {}
 unsafe impl ByteValued for CreateIn {}
 unsafe impl ByteValued for MkdirIn {}
+unsafe impl ByteValued for RenameIn {}
+unsafe impl ByteValued for Rename2In {}
+unsafe impl ByteValued for LinkIn {}
 unsafe impl ByteValued for OpenIn {}
 unsafe impl ByteValued for OpenOut {}
 unsafe impl ByteValued for WriteIn {}
 unsafe impl ByteValued for WriteOut {}
 unsafe impl ByteValued for ReadIn {}
+unsafe impl ByteValued for ReleaseIn {}
+unsafe impl ByteValued for FlushIn {}
+unsafe impl ByteValued for FsyncIn {}
+unsafe impl ByteValued for CopyFileRangeIn {}
+unsafe impl ByteValued for InterruptIn {}
+unsafe impl ByteValued for AccessIn {}
+unsafe impl ByteValued for IoctlIn {}
+unsafe impl ByteValued for IoctlIovec {}
+unsafe impl ByteValued for IoctlOut {}
+unsafe impl ByteValued for PollIn {}
+unsafe impl ByteValued for PollOut {}
+unsafe impl ByteValued for GetxattrIn {}
+unsafe impl ByteValued for GetxattrOut {}
+unsafe impl ByteValued for FallocateIn {}
+unsafe impl ByteValued for LseekIn {}
+unsafe impl ByteValued for LseekOut {}
+unsafe impl ByteValued for SetupMappingIn {}
+unsafe impl ByteValued for RemoveMappingIn {}
+unsafe impl ByteValued for RemoveMappingOne {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+        let header = InHeader {
+            len: (size_of::<InHeader>() + payload.len()) as u32,
+            opcode: opcode as u32,
+            unique: 1,
+            nodeid: 1,
+            ..Default::default()
+        };
+        [header.as_slice(), payload].concat()
+    }
+
+    fn with_len(mut buf: Vec<u8>, len: usize) -> Vec<u8> {
+        buf[..4].copy_from_slice(&(len as u32).to_le_bytes());
+        buf
+    }
+
+    fn errno(buf: &[u8]) -> Option<i32> {
+        decode_request(buf)
+            .err()
+            .map(|err| err.errno().unwrap_or(0))
+    }
+
+    #[test]
+    fn test_decode_well_formed() {
+        let lookup = request(Opcode::Lookup, b"file\0");
+        let decoded = decode_request(&lookup).unwrap();
+        assert!(matches!(decoded.opcode, Opcode::Lookup));
+        assert_eq!(decoded.header.unique, 1);
+        assert_eq!(decoded.payload, b"file\0");
+
+        let write = WriteIn {
+            size: 4,
+            ..Default::default()
+        };
+        let write = request(Opcode::Write, &[write.as_slice(), b"data"].concat());
+        assert!(decode_request(&write).is_ok());
+    }
+
+    #[test]
+    fn test_decode_truncated_header() {
+        let lookup = request(Opcode::Lookup, b"file\0");
+        assert_eq!(errno(&[]), Some(libc::EIO));
+        assert_eq!(errno(&lookup[..size_of::<InHeader>() / 2]), Some(libc::EIO));
+    }
+
+    #[test]
+    fn test_decode_unknown_opcode() {
+        for opcode in [0, 8, u32::MAX] {
+            let mut buf = request(Opcode::Getattr, &[]);
+            buf[4..8].copy_from_slice(&opcode.to_le_bytes());
+            assert_eq!(errno(&buf), Some(libc::ENOSYS));
+        }
+    }
+
+    #[test]
+    fn test_decode_length_mismatch() {
+        let lookup = request(Opcode::Lookup, b"file\0");
+        let len = lookup.len();
+        assert_eq!(errno(&with_len(lookup.clone(), len + 1)), Some(libc::EIO));
+        assert_eq!(errno(&with_len(lookup, len - 1)), Some(libc::EIO));
+    }
+
+    #[test]
+    fn test_decode_short_request() {
+        let lookup = request(Opcode::Lookup, b"file\0");
+        assert_eq!(errno(&with_len(lookup, 4)), Some(libc::EINVAL));
+        let create = request(Opcode::Create, &[0; size_of::<CreateIn>()]);
+        let len = size_of::<InHeader>() + 8;
+        assert_eq!(errno(&with_len(create, len)), Some(libc::EINVAL));
+        assert_eq!(errno(&request(Opcode::Init, &[0; 8])), Some(libc::EINVAL));
+    }
+
+    #[test]
+    fn test_decode_trailing_past_payload() {
+        let write = WriteIn {
+            size: 8,
+            ..Default::default()
+        };
+        let write = request(Opcode::Write, &[write.as_slice(), b"data"].concat());
+        assert_eq!(errno(&write), Some(libc::EIO));
+
+        let forget = BatchForgetIn {
+            count: u32::MAX,
+            dummy: 0,
+        };
+        assert_eq!(
+            errno(&request(Opcode::BatchForget, forget.as_slice())),
+            Some(libc::EIO)
+        );
+
+        let remove = RemoveMappingIn { count: 2 };
+        let one = RemoveMappingOne::default();
+        let remove = request(
+            Opcode::RemoveMapping,
+            &[remove.as_slice(), one.as_slice()].concat(),
+        );
+        assert_eq!(errno(&remove), Some(libc::EIO));
+    }
+
+    #[test]
+    fn test_decode_names_without_nul() {
+        assert!(decode_request(&request(Opcode::Lookup, b"file")).is_ok());
+        let rename = RenameIn { newdir: 1 };
+        let rename = request(Opcode::Rename, &[rename.as_slice(), b"old\0new"].concat());
+        assert!(decode_request(&rename).is_ok());
+    }
+}
@@ -0,0 +1,22 @@
+//! virtiofs backend serving an OpenDAL operator to a VMM over vhost-user.
+
+mod buffer;
+mod cache;
+mod compression;
+mod content_type;
+mod daemon;
+pub mod error;
+mod filesystem;
+mod filesystem_message;
+pub mod logger;
+mod metrics;
+mod util;
+
+pub use compression::Compression;
+pub use content_type::ContentTypes;
+pub use daemon::Ovfs;
+pub use daemon::OvfsBuilder;
+pub use daemon::OvfsHandle;
+pub use filesystem::FilesystemConfig;
+pub use filesystem_message::decode_request;
+pub use filesystem_message::DecodedRequest;
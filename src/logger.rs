@@ -0,0 +1,82 @@
+use std::fs::rename;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+use env_logger::Builder;
+use env_logger::Target;
+use log::LevelFilter;
+
+use crate::error::*;
+
+/// Log file that is moved to `<path>.1` once it grows past `max_size` bytes.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            max_size,
+            file,
+            size,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+        rename(&self.path, rotated)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_size > 0 && self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let len = self.file.write(buf)?;
+        self.size += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Initializes the global logger, writing to stderr unless a log file is given.
+///
+/// `RUST_LOG` is still honoured, a level overrides its default level.
+pub fn init(level: Option<LevelFilter>, file: Option<&Path>, max_size: u64) -> Result<()> {
+    let mut builder = Builder::from_default_env();
+    if let Some(level) = level {
+        builder.filter_level(level);
+    }
+    if let Some(path) = file {
+        let file = RotatingFile::open(path, max_size).map_err(|err| {
+            new_unexpected_error(
+                &format!("failed to open log file {}", path.display()),
+                Some(err.into()),
+            )
+        })?;
+        builder.target(Target::Pipe(Box::new(file)));
+    }
+    builder.init();
+    Ok(())
+}
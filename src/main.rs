@@ -1,301 +1,631 @@
-use std::io;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::Path;
+use std::path::PathBuf;
 use std::process::exit;
 use std::str::FromStr;
-use std::sync::Arc;
-use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
 
+use clap::builder::RangedU64ValueParser;
 use clap::Parser;
+use futures::TryStreamExt;
 use log::error;
-use log::warn;
+use log::info;
+use log::LevelFilter;
+use opendal::layers::RetryLayer;
+use opendal::layers::TimeoutLayer;
 use opendal::Operator;
 use opendal::Scheme;
+use ovfs::error::*;
+use ovfs::logger;
+use ovfs::Compression;
+use ovfs::ContentTypes;
+use ovfs::FilesystemConfig;
+use ovfs::OvfsBuilder;
 use url::Url;
-use vhost::vhost_user::message::VhostUserProtocolFeatures;
-use vhost::vhost_user::message::VhostUserVirtioFeatures;
-use vhost::vhost_user::Backend;
-use vhost::vhost_user::Listener;
-use vhost_user_backend::VhostUserBackend;
-use vhost_user_backend::VhostUserDaemon;
-use vhost_user_backend::VringMutex;
-use vhost_user_backend::VringState;
-use vhost_user_backend::VringT;
-use virtio_bindings::bindings::virtio_config::VIRTIO_F_VERSION_1;
-use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
-use virtio_bindings::bindings::virtio_ring::VIRTIO_RING_F_INDIRECT_DESC;
-use virtio_queue::DescriptorChain;
-use virtio_queue::QueueOwnedT;
-use vm_memory::GuestAddressSpace;
-use vm_memory::GuestMemoryAtomic;
-use vm_memory::GuestMemoryLoadGuard;
-use vm_memory::GuestMemoryMmap;
-use vmm_sys_util::epoll::EventSet;
-use vmm_sys_util::eventfd::EventFd;
-
-mod buffer;
-mod error;
-mod filesystem;
-mod filesystem_message;
-mod util;
-
-use crate::error::*;
-use crate::filesystem::Filesystem;
-use crate::util::Reader;
-use crate::util::Writer;
-
-const HIPRIO_QUEUE_EVENT: u16 = 0;
-const REQ_QUEUE_EVENT: u16 = 1;
-const QUEUE_SIZE: usize = 1024;
-const REQUEST_QUEUES: usize = 1;
-const NUM_QUEUES: usize = REQUEST_QUEUES + 1;
-
-struct VhostUserFsThread {
-    mem: Option<GuestMemoryAtomic<GuestMemoryMmap>>,
-    server: Filesystem,
-    vu_req: Option<Backend>,
-    event_idx: bool,
-    kill_event_fd: EventFd,
-}
 
-impl VhostUserFsThread {
-    fn new(fs: Filesystem) -> Result<VhostUserFsThread> {
-        let event_fd = EventFd::new(libc::EFD_NONBLOCK).map_err(|err| {
-            new_unexpected_error("failed to create kill eventfd", Some(err.into()))
-        })?;
-        Ok(VhostUserFsThread {
-            mem: None,
-            server: fs,
-            vu_req: None,
-            event_idx: false,
-            kill_event_fd: event_fd,
-        })
-    }
+const DEFAULT_WORKER_THREADS: usize = 4;
+const DEFAULT_NEGATIVE_LOOKUP_TTL_MS: u64 = 1000;
+const DEFAULT_TIMEOUT_MS: u64 = 1000;
+const DEFAULT_MAX_BUFFER_SIZE: u32 = 1 << 20;
+const DEFAULT_WRITE_BUFFER_SIZE: usize = 4 << 20;
+const DEFAULT_OWNER: &str = "1000:1000";
+const DEFAULT_RETRY_MAX_TIMES: usize = 3;
+const DEFAULT_RETRY_MIN_DELAY_MS: u64 = 100;
+const DEFAULT_OP_TIMEOUT_MS: u64 = 60_000;
+const DEFAULT_IO_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_LOG_FILE_SIZE: u64 = 64 << 20;
+const DEFAULT_MAX_INODES: usize = 1 << 20;
+const DEFAULT_MAX_FILE_SIZE: u64 = 1 << 30;
+const MIN_MAX_BUFFER_SIZE: u32 = 1 << 12;
+const MAX_MAX_BUFFER_SIZE: u32 = 1 << 24;
 
-    fn return_descriptor(
-        vring_state: &mut VringState,
-        head_index: u16,
-        event_idx: bool,
-        len: usize,
-    ) {
-        let used_len: u32 = match len.try_into() {
-            Ok(l) => l,
-            Err(_) => {
-                error!("invalid used length, can't return used descritors to the ring");
-                exit(1);
-            }
-        };
-        if vring_state.add_used(head_index, used_len).is_err() {
-            warn!("couldn't return used descriptors to the ring");
-        }
-        if event_idx {
-            match vring_state.needs_notification() {
-                Err(_) => {
-                    warn!("couldn't check if queue needs to be notified");
-                    vring_state.signal_used_queue().unwrap();
-                }
-                Ok(needs_notification) => {
-                    if needs_notification {
-                        vring_state.signal_used_queue().unwrap();
-                    }
-                }
-            }
-        } else {
-            vring_state.signal_used_queue().unwrap();
-        }
-    }
-
-    fn process_queue_serial(&self, vring_state: &mut VringState) -> Result<bool> {
-        let mut used_any = false;
-        let mem = match &self.mem {
-            Some(m) => m.memory(),
-            None => return Err(new_unexpected_error("no memory configured", None)),
-        };
-        let avail_chains: Vec<DescriptorChain<GuestMemoryLoadGuard<GuestMemoryMmap>>> = vring_state
-            .get_queue_mut()
-            .iter(mem.clone())
-            .map_err(|_| new_unexpected_error("iterating through the queue failed", None))?
-            .collect();
-        for chain in avail_chains {
-            used_any = true;
-            let head_index = chain.head_index();
-            let reader = Reader::new(&mem, chain.clone())
-                .map_err(|_| new_unexpected_error("creating a queue reader failed", None))
-                .unwrap();
-            let writer = Writer::new(&mem, chain.clone())
-                .map_err(|_| new_unexpected_error("creating a queue writer failed", None))
-                .unwrap();
-            let len = self
-                .server
-                .handle_message(reader, writer)
-                .map_err(|_| new_unexpected_error("processing a queue writer failed", None))
-                .unwrap();
-            VhostUserFsThread::return_descriptor(vring_state, head_index, self.event_idx, len);
-        }
-        Ok(used_any)
-    }
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Config {
+    #[arg(env = "OVFS_SOCKET_PATH", index = 1)]
+    socket_path: String,
 
-    fn handle_event_serial(&self, device_event: u16, vrings: &[VringMutex]) -> Result<()> {
-        let mut vring_state = match device_event {
-            HIPRIO_QUEUE_EVENT => vrings[0].get_mut(),
-            REQ_QUEUE_EVENT => vrings[1].get_mut(),
-            _ => return Err(new_unexpected_error("failed to handle unknown event", None)),
-        };
-        if self.event_idx {
-            loop {
-                vring_state.disable_notification().unwrap();
-                self.process_queue_serial(&mut vring_state)?;
-                if !vring_state.enable_notification().unwrap() {
-                    break;
-                }
-            }
-        } else {
-            self.process_queue_serial(&mut vring_state)?;
-        }
-        Ok(())
-    }
-}
+    #[arg(env = "OVFS_BACKEND", index = 2)]
+    backend: Url,
 
-struct VhostUserFsBackend {
-    thread: RwLock<VhostUserFsThread>,
+    /// Number of worker threads of the runtime driving backend requests.
+    #[arg(
+        long,
+        env = "OVFS_WORKER_THREADS",
+        default_value_t = DEFAULT_WORKER_THREADS,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    worker_threads: usize,
+
+    /// Size in bytes fetched ahead on each backend read and cached for sequential reads, 0 disables it.
+    #[arg(long, env = "OVFS_READ_AHEAD_SIZE", default_value_t = 0)]
+    read_ahead_size: u64,
+
+    /// Milliseconds a failed lookup is remembered and answered without asking the backend, 0 disables it.
+    #[arg(long, env = "OVFS_NEGATIVE_LOOKUP_TTL", default_value_t = DEFAULT_NEGATIVE_LOOKUP_TTL_MS)]
+    negative_lookup_ttl: u64,
+
+    /// Milliseconds a directory listing is reused for later readdir calls, 0 disables it.
+    #[arg(long, env = "OVFS_DIR_CACHE_TTL", default_value_t = DEFAULT_TIMEOUT_MS)]
+    dir_cache_ttl: u64,
+
+    /// Milliseconds the guest kernel may cache name lookups, 0 disables the cache.
+    #[arg(long, env = "OVFS_ENTRY_TIMEOUT", default_value_t = DEFAULT_TIMEOUT_MS)]
+    entry_timeout: u64,
+
+    /// Milliseconds the guest kernel may cache file attributes, 0 disables the cache.
+    #[arg(long, env = "OVFS_ATTR_TIMEOUT", default_value_t = DEFAULT_TIMEOUT_MS)]
+    attr_timeout: u64,
+
+    /// Largest read or write request in bytes, a power of two between 4KiB and 16MiB.
+    #[arg(long, env = "OVFS_MAX_BUFFER_SIZE", default_value_t = DEFAULT_MAX_BUFFER_SIZE, value_parser = parse_max_buffer_size)]
+    max_buffer_size: u32,
+
+    /// Bytes of sequential writes gathered before they are sent to the backend, 0 sends every write.
+    #[arg(long, env = "OVFS_WRITE_BUFFER_SIZE", default_value_t = DEFAULT_WRITE_BUFFER_SIZE)]
+    write_buffer_size: usize,
+
+    /// Expose the backend read-only, rejecting every modification with EROFS.
+    #[arg(long, env = "OVFS_READ_ONLY")]
+    read_only: bool,
+
+    /// Map file contents into the guest's DAX window instead of copying reads, when the guest has one.
+    ///
+    /// Mappings are read-only, so it suits read-mostly workloads.
+    #[arg(long, env = "OVFS_ENABLE_DAX")]
+    enable_dax: bool,
+
+    /// Let the guest kernel cache writes and send them to ovfs in larger batches.
+    ///
+    /// Only used with backends that can write an object in several parts.
+    #[arg(long, env = "OVFS_WRITEBACK")]
+    writeback: bool,
+
+    /// Check every file written through a handle against the backend once it is closed, failing the close with EIO on a mismatch.
+    ///
+    /// Objects are read back unless the backend reports their MD5 as the ETag, so it roughly doubles the cost of writes.
+    #[arg(long, env = "OVFS_VERIFY_WRITES")]
+    verify_writes: bool,
+
+    /// Compress files with gzip or zstd before they are stored, marking them in their user metadata.
+    ///
+    /// Compressed files are kept in memory while open for writing and read back whole, so it suits small files. Objects already marked as compressed are read whatever the option, on backends without user metadata files are stored as is.
+    #[arg(long, env = "OVFS_COMPRESS")]
+    compress: Option<Compression>,
+
+    /// Write objects with a Content-Type inferred from their file extension, e.g. text/html for index.html, on backends storing one.
+    #[arg(long, env = "OVFS_INFER_CONTENT_TYPE")]
+    infer_content_type: bool,
+
+    /// Content type in extension=type form written for an extension in place of the built-in one, implying --infer-content-type.
+    #[arg(long = "content-type", value_parser = parse_content_type)]
+    content_types: Vec<(String, String)>,
+
+    /// Subdirectory of the backend exposed as the root, e.g. a per tenant prefix.
+    #[arg(long, env = "OVFS_SUBDIR", value_parser = parse_subdir)]
+    subdir: Option<String>,
+
+    /// Report 2 plus the number of subdirectories as a directory's link count, at the cost of a list per stat.
+    #[arg(long, env = "OVFS_COUNT_SUBDIRS")]
+    count_subdirs: bool,
+
+    /// Return attributes along with directory entries, so listing a directory doesn't cost a lookup per entry.
+    ///
+    /// Listings don't carry symlink targets, so symlinks not yet looked up are listed as regular files.
+    #[arg(long, env = "OVFS_READDIRPLUS")]
+    readdirplus: bool,
+
+    /// Look up a name missing in its exact case among the entries of its directory, for data from case-insensitive filesystems.
+    ///
+    /// Each such miss lists the directory, and an exact match is always preferred.
+    #[arg(long, env = "OVFS_CASE_INSENSITIVE")]
+    case_insensitive: bool,
+
+    /// Times a backend request failing with a temporary error, e.g. throttling, is retried, 0 disables retries.
+    #[arg(long, env = "OVFS_RETRY_MAX_TIMES", default_value_t = DEFAULT_RETRY_MAX_TIMES)]
+    retry_max_times: usize,
+
+    /// Milliseconds before the first retry, doubled with jitter on every following one.
+    #[arg(long, env = "OVFS_RETRY_MIN_DELAY", default_value_t = DEFAULT_RETRY_MIN_DELAY_MS)]
+    retry_min_delay: u64,
+
+    /// Milliseconds a backend request such as a stat, delete or the start of a listing may take, retries included.
+    #[arg(long, env = "OVFS_OP_TIMEOUT", default_value_t = DEFAULT_OP_TIMEOUT_MS)]
+    op_timeout: u64,
+
+    /// Milliseconds each part of a read, write or listing may take, so a large listing isn't cut off by --op-timeout.
+    #[arg(long, env = "OVFS_IO_TIMEOUT", default_value_t = DEFAULT_IO_TIMEOUT_MS)]
+    io_timeout: u64,
+
+    /// Owner reported for every file, in uid:gid form.
+    #[arg(long, env = "OVFS_OWNER", default_value = DEFAULT_OWNER, value_parser = parse_owner)]
+    owner: (u32, u32),
+
+    /// Report every file as owned by the guest process accessing it, overriding --owner.
+    #[arg(long, env = "OVFS_PASSTHROUGH_OWNER")]
+    passthrough_owner: bool,
+
+    /// Write logs to this file instead of stderr.
+    #[arg(long, env = "OVFS_LOG_FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Bytes a log file grows to before it is moved to <log-file>.1 and started again, 0 never rotates.
+    #[arg(long, env = "OVFS_LOG_FILE_SIZE", default_value_t = DEFAULT_LOG_FILE_SIZE)]
+    log_file_size: u64,
+
+    /// Lowest level logged, e.g. info or debug, overriding the default level of RUST_LOG.
+    #[arg(long, env = "OVFS_LOG_LEVEL")]
+    log_level: Option<LevelFilter>,
+
+    /// Check that the backend is reachable with the given options and exit instead of serving.
+    ///
+    /// The socket path is not used, so this can run before the VM is launched.
+    #[arg(long)]
+    check: bool,
+
+    /// Unix socket answering every connection with a JSON dump of request, inode, handle and backend error counters.
+    #[arg(long, env = "OVFS_CONTROL_SOCKET")]
+    control_socket: Option<PathBuf>,
+
+    /// Inodes cached before the least recently used ones the guest no longer references are
+    /// evicted, 0 never evicts.
+    #[arg(long, env = "OVFS_MAX_INODES", default_value_t = DEFAULT_MAX_INODES)]
+    max_inodes: usize,
+
+    /// Largest size in bytes a file may be truncated or written to when ovfs has to rebuild it in memory, larger ones fail with EFBIG.
+    ///
+    /// Sequential writes stream to the backend and are not limited.
+    #[arg(long, env = "OVFS_MAX_FILE_SIZE", default_value_t = DEFAULT_MAX_FILE_SIZE)]
+    max_file_size: u64,
+
+    /// Stat up to this many entries of each listed page at once, so the lookups that follow a listing don't wait on the backend one by one, 0 disables it.
+    ///
+    /// Prefetched stats are used once and for at most --attr-timeout.
+    #[arg(long, env = "OVFS_PREFETCH_STATS", default_value_t = 0)]
+    prefetch_stats: usize,
+
+    /// Extra backend option in key=value form, overriding the backend url query.
+    #[arg(long = "backend-option", value_parser = parse_backend_option)]
+    backend_options: Vec<(String, String)>,
 }
 
-impl VhostUserFsBackend {
-    fn new(fs: Filesystem) -> Result<VhostUserFsBackend> {
-        let thread = RwLock::new(VhostUserFsThread::new(fs)?);
-        Ok(VhostUserFsBackend { thread })
+fn parse_backend_option(option: &str) -> Result<(String, String), String> {
+    match option.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!(
+            "invalid backend option: {}, expected key=value",
+            option
+        )),
     }
 }
 
-impl VhostUserBackend for VhostUserFsBackend {
-    type Bitmap = ();
-    type Vring = VringMutex;
-
-    fn num_queues(&self) -> usize {
-        NUM_QUEUES
+fn parse_content_type(option: &str) -> Result<(String, String), String> {
+    match option.split_once('=') {
+        Some((ext, content_type)) if !ext.is_empty() && !content_type.is_empty() => {
+            Ok((ext.to_string(), content_type.to_string()))
+        }
+        _ => Err(format!(
+            "invalid content type: {}, expected extension=type",
+            option
+        )),
     }
+}
 
-    fn max_queue_size(&self) -> usize {
-        QUEUE_SIZE
-    }
+fn parse_owner(owner: &str) -> Result<(u32, u32), String> {
+    owner
+        .split_once(':')
+        .and_then(|(uid, gid)| Some((uid.parse().ok()?, gid.parse().ok()?)))
+        .ok_or(format!("invalid owner: {}, expected uid:gid", owner))
+}
 
-    fn features(&self) -> u64 {
-        1 << VIRTIO_F_VERSION_1
-            | 1 << VIRTIO_RING_F_INDIRECT_DESC
-            | 1 << VIRTIO_RING_F_EVENT_IDX
-            | VhostUserVirtioFeatures::PROTOCOL_FEATURES.bits()
+fn parse_subdir(subdir: &str) -> Result<String, String> {
+    let components: Vec<&str> = subdir.split('/').filter(|c| !c.is_empty()).collect();
+    if components.iter().any(|c| *c == "." || *c == "..") {
+        return Err(format!(
+            "invalid subdir: {}, relative components are not allowed",
+            subdir
+        ));
     }
+    Ok(components.join("/"))
+}
 
-    fn protocol_features(&self) -> VhostUserProtocolFeatures {
-        let protocol_features = VhostUserProtocolFeatures::MQ
-            | VhostUserProtocolFeatures::BACKEND_REQ
-            | VhostUserProtocolFeatures::BACKEND_SEND_FD
-            | VhostUserProtocolFeatures::REPLY_ACK
-            | VhostUserProtocolFeatures::CONFIGURE_MEM_SLOTS;
-        protocol_features
+fn parse_max_buffer_size(size: &str) -> Result<u32, String> {
+    let size: u32 = size
+        .parse()
+        .map_err(|_| format!("invalid buffer size: {}", size))?;
+    if !size.is_power_of_two() || !(MIN_MAX_BUFFER_SIZE..=MAX_MAX_BUFFER_SIZE).contains(&size) {
+        return Err(format!(
+            "buffer size must be a power of two between {} and {}",
+            MIN_MAX_BUFFER_SIZE, MAX_MAX_BUFFER_SIZE
+        ));
     }
+    Ok(size)
+}
 
-    fn set_event_idx(&self, enabled: bool) {
-        self.thread.write().unwrap().event_idx = enabled;
-    }
+fn build_backend(
+    scheme: Scheme,
+    op_args: &HashMap<String, String>,
+    retry: &RetryLayer,
+    timeout: &TimeoutLayer,
+) -> Result<Operator> {
+    let backend = Operator::via_iter(scheme, op_args.clone()).map_err(Error::from)?;
+    // the timeout wraps the retries, so a stalled backend fails the guest's request in time.
+    Ok(backend.layer(retry.clone()).layer(timeout.clone()))
+}
 
-    fn update_memory(&self, mem: GuestMemoryAtomic<GuestMemoryMmap>) -> io::Result<()> {
-        self.thread.write().unwrap().mem = Some(mem);
-        Ok(())
-    }
+fn check_backend(backend: &Operator) -> Result<(), String> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| format!("failed to create runtime: {}", err))?;
+    // stat on the root is answered without a request, listing reaches the service.
+    rt.block_on(async {
+        let mut lister = backend.lister("/").await?;
+        lister.try_next().await
+    })
+    .map(|_| ())
+    .map_err(|err| err.to_string())
+}
 
-    fn handle_event(
-        &self,
-        device_event: u16,
-        evset: EventSet,
-        vrings: &[VringMutex],
-        _thread_id: usize,
-    ) -> io::Result<()> {
-        if evset != EventSet::IN {
+fn check_backend_options(scheme: Scheme, op_args: &HashMap<String, String>) -> Result<()> {
+    let required: &[&str] = match scheme {
+        Scheme::Fs => &["root"],
+        Scheme::S3 | Scheme::Gcs => &["bucket"],
+        Scheme::Azblob => &["container"],
+        _ => &[],
+    };
+    for key in required {
+        if !op_args.contains_key(*key) {
             return Err(new_unexpected_error(
-                "failed to handle event other than input event",
+                &format!("{} backend requires option {}", scheme, key),
                 None,
-            )
-            .into());
+            ));
         }
-        let thread = self.thread.read().unwrap();
-        thread
-            .handle_event_serial(device_event, vrings)
-            .map_err(|err| err.into())
     }
+    Ok(())
+}
 
-    fn exit_event(&self, _thread_index: usize) -> Option<EventFd> {
-        Some(
-            self.thread
-                .read()
-                .unwrap()
-                .kill_event_fd
-                .try_clone()
-                .unwrap(),
-        )
+fn check_socket_path(socket_path: &str) -> Result<()> {
+    let dir = match Path::new(socket_path).parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Err(new_unexpected_error(
+            &format!("socket directory {} does not exist", dir.display()),
+            None,
+        ));
     }
-
-    fn set_backend_req_fd(&self, vu_req: Backend) {
-        self.thread.write().unwrap().vu_req = Some(vu_req);
+    let c_dir = CString::new(dir.as_os_str().as_encoded_bytes())
+        .map_err(|err| new_unexpected_error("invalid socket path", Some(err.into())))?;
+    if unsafe { libc::access(c_dir.as_ptr(), libc::W_OK) } != 0 {
+        return Err(new_unexpected_error(
+            &format!("socket directory {} is not writable", dir.display()),
+            None,
+        ));
     }
+    Ok(())
 }
 
-#[derive(Parser, Debug)]
-#[command(version, about)]
-struct Config {
-    #[arg(env = "OVFS_SOCKET_PATH", index = 1)]
-    socket_path: String,
+fn check_fs_root(op_args: &HashMap<String, String>) -> Result<()> {
+    let root = op_args
+        .get("root")
+        .ok_or(new_unexpected_error("fs backend requires a root", None))?;
+    if !Path::new(root).is_dir() {
+        return Err(new_unexpected_error(
+            &format!("fs backend root {} is not a directory", root),
+            None,
+        ));
+    }
+    Ok(())
+}
 
-    #[arg(env = "OVFS_BACKEND", index = 2)]
-    backend: Url,
+fn block_signals() -> libc::sigset_t {
+    unsafe {
+        let mut signals = std::mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGHUP);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+        signals
+    }
 }
 
 fn main() {
-    env_logger::init();
-
     let cfg = Config::parse();
+    if let Err(e) = logger::init(cfg.log_level, cfg.log_file.as_deref(), cfg.log_file_size) {
+        eprintln!("failed to initialize logger: {}", e);
+        exit(1);
+    }
+
+    // block the handled signals before any thread is spawned so only the signal thread sees them.
+    let signals = block_signals();
+
     if cfg.backend.has_host() {
         log::warn!("backend host will be ignored");
     }
 
     let scheme_str = cfg.backend.scheme();
-    let op_args = cfg.backend.query_pairs().into_owned();
+    let mut op_args: HashMap<String, String> = cfg.backend.query_pairs().into_owned().collect();
+    op_args.extend(cfg.backend_options);
 
     let scheme = match Scheme::from_str(scheme_str) {
         Ok(Scheme::Custom(_)) | Err(_) => {
             log::error!("invalid backend scheme: {}", scheme_str);
-            return;
+            exit(1);
         }
         Ok(s) => s,
     };
-    let backend = Operator::via_iter(scheme, op_args).unwrap();
 
-    let listener = Listener::new(cfg.socket_path, true).unwrap();
-    let fs = Filesystem::new(backend);
-    let fs_backend = Arc::new(VhostUserFsBackend::new(fs).unwrap());
+    if let Err(e) = check_backend_options(scheme, &op_args) {
+        error!("invalid backend: {}", e);
+        exit(1);
+    }
+    if scheme == Scheme::Fs {
+        if let Err(e) = check_fs_root(&op_args) {
+            error!("invalid backend: {}", e);
+            exit(1);
+        }
+    }
+    // OpenDAL resolves every path against the root, so scoping the root keeps the guest inside it.
+    if let Some(subdir) = cfg.subdir.as_ref().filter(|subdir| !subdir.is_empty()) {
+        let root = op_args
+            .get("root")
+            .map_or("", |root| root.trim_end_matches('/'));
+        let root = format!("{}/{}/", root, subdir);
+        op_args.insert("root".to_string(), root);
+    }
+    let retry = RetryLayer::new()
+        .with_max_times(cfg.retry_max_times)
+        .with_min_delay(Duration::from_millis(cfg.retry_min_delay))
+        .with_jitter();
+    let timeout = TimeoutLayer::new()
+        .with_timeout(Duration::from_millis(cfg.op_timeout))
+        .with_io_timeout(Duration::from_millis(cfg.io_timeout));
+    let backend = match build_backend(scheme, &op_args, &retry, &timeout) {
+        Ok(backend) => backend,
+        Err(e) => {
+            error!("failed to build backend: {}", e);
+            exit(1);
+        }
+    };
 
-    let mut daemon = VhostUserDaemon::new(
-        String::from("ovfs-backend"),
-        fs_backend.clone(),
-        GuestMemoryAtomic::new(GuestMemoryMmap::new()),
-    )
-    .unwrap();
+    if cfg.check {
+        match check_backend(&backend) {
+            Ok(()) => {
+                println!("{} backend is reachable", scheme);
+                exit(0);
+            }
+            Err(e) => {
+                eprintln!("{} backend check failed: {}", scheme, e);
+                exit(1);
+            }
+        }
+    }
 
-    if let Err(e) = daemon.start(listener) {
-        error!("failed to start daemon: {:?}", e);
+    if let Err(e) = check_socket_path(&cfg.socket_path) {
+        error!("invalid socket path: {}", e);
+        exit(1);
+    }
+
+    let config = FilesystemConfig {
+        worker_threads: cfg.worker_threads,
+        read_ahead_size: cfg.read_ahead_size,
+        negative_lookup_ttl: Duration::from_millis(cfg.negative_lookup_ttl),
+        dir_cache_ttl: Duration::from_millis(cfg.dir_cache_ttl),
+        entry_timeout: Duration::from_millis(cfg.entry_timeout),
+        attr_timeout: Duration::from_millis(cfg.attr_timeout),
+        max_buffer_size: cfg.max_buffer_size,
+        write_buffer_size: cfg.write_buffer_size,
+        read_only: cfg.read_only,
+        dax: cfg.enable_dax,
+        writeback: cfg.writeback,
+        verify_writes: cfg.verify_writes,
+        compression: cfg.compress,
+        content_types: (cfg.infer_content_type || !cfg.content_types.is_empty())
+            .then(|| ContentTypes::new(cfg.content_types)),
+        count_subdirs: cfg.count_subdirs,
+        readdirplus: cfg.readdirplus,
+        case_insensitive: cfg.case_insensitive,
+        uid: cfg.owner.0,
+        gid: cfg.owner.1,
+        passthrough_owner: cfg.passthrough_owner,
+        max_inodes: cfg.max_inodes,
+        max_file_size: cfg.max_file_size,
+        prefetch_stats: cfg.prefetch_stats,
+    };
+    let mut builder = OvfsBuilder::new(backend, &cfg.socket_path, config);
+    if let Some(control_socket) = cfg.control_socket {
+        builder = builder.control_socket(control_socket);
+    }
+    let mut daemon = match builder.build() {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            error!("failed to build daemon: {}", e);
+            exit(1);
+        }
+    };
+
+    let handle = daemon.handle();
+    thread::spawn(move || loop {
+        let mut signal = 0;
+        if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+            error!("failed to wait for signals");
+            return;
+        }
+        if signal == libc::SIGHUP {
+            // rebuilding the operator picks up rotated backend credentials.
+            info!("received signal {}, refreshing backend", signal);
+            match build_backend(scheme, &op_args, &retry, &timeout) {
+                Ok(backend) => handle.refresh_operator(backend),
+                Err(e) => error!("failed to rebuild backend: {}", e),
+            }
+            continue;
+        }
+        info!("received signal {}, shutting down", signal);
+        if let Err(e) = handle.shutdown() {
+            error!("failed to shutdown: {}", e);
+            exit(1);
+        }
+        exit(0);
+    });
+
+    if let Err(e) = daemon.start() {
+        error!("{}", e);
         exit(1);
     }
 
     if let Err(e) = daemon.wait() {
-        error!("failed to wait for daemon: {:?}", e);
+        error!("{}", e);
+    }
+
+    if let Err(e) = daemon.shutdown() {
+        error!("failed to shutdown: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use super::*;
+
+    // answers the first `failures` requests with 503 and later ones with `data`, returning the
+    // endpoint and the number of requests served.
+    fn serve(failures: usize, data: &'static str) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let served = requests.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                let status = if served.fetch_add(1, Ordering::SeqCst) < failures {
+                    "503 Service Unavailable"
+                } else {
+                    "200 OK"
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    data.len(),
+                    data
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (endpoint, requests)
+    }
+
+    // accepts connections and never answers them.
+    fn stall() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut streams = Vec::new();
+            for stream in listener.incoming() {
+                streams.push(stream);
+            }
+        });
+        endpoint
+    }
+
+    fn s3(endpoint: &str, retry_max_times: usize, op_timeout: Duration) -> Operator {
+        let op_args = HashMap::from(
+            [
+                ("bucket", "ovfs"),
+                ("endpoint", endpoint),
+                ("region", "us-east-1"),
+                ("access_key_id", "ovfs"),
+                ("secret_access_key", "ovfs"),
+                ("disable_config_load", "true"),
+                ("disable_ec2_metadata", "true"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        let retry = RetryLayer::new()
+            .with_max_times(retry_max_times)
+            .with_min_delay(Duration::from_millis(10));
+        let timeout = TimeoutLayer::new()
+            .with_timeout(op_timeout)
+            .with_io_timeout(op_timeout);
+        build_backend(Scheme::S3, &op_args, &retry, &timeout).unwrap()
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_retry_until_success() {
+        let (endpoint, requests) = serve(2, "data");
+        let backend = s3(&endpoint, 3, Duration::from_secs(10));
+        let data = runtime().block_on(backend.read("file")).unwrap();
+        assert_eq!(data.to_vec(), b"data");
+        assert!(requests.load(Ordering::SeqCst) > 2);
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let (endpoint, requests) = serve(usize::MAX, "");
+        let backend = s3(&endpoint, 2, Duration::from_secs(10));
+        let err = runtime().block_on(backend.stat("file")).unwrap_err();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        // an error the retries gave up on is not a timeout.
+        assert_eq!(Error::from(err).errno(), Some(libc::EIO));
+    }
+
+    #[test]
+    fn test_stalled_backend_times_out() {
+        let backend = s3(&stall(), 3, Duration::from_millis(200));
+        let start = Instant::now();
+        let err = runtime().block_on(backend.stat("file")).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(Error::from(err).errno(), Some(libc::ETIMEDOUT));
     }
 
-    let kill_event_fd = fs_backend
-        .thread
-        .read()
-        .unwrap()
-        .kill_event_fd
-        .try_clone()
-        .unwrap();
-    if let Err(e) = kill_event_fd.write(1) {
-        error!("failed to shutdown worker thread: {:?}", e);
+    #[test]
+    fn test_timeout_wraps_retries() {
+        let (endpoint, _) = serve(usize::MAX, "");
+        // the retries alone would take far longer than the timeout.
+        let backend = s3(&endpoint, 100, Duration::from_millis(200));
+        let start = Instant::now();
+        let err = runtime().block_on(backend.stat("file")).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(Error::from(err).errno(), Some(libc::ETIMEDOUT));
     }
 }
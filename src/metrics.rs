@@ -0,0 +1,166 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const MAX_OPCODES: usize = 64;
+// upper bounds in microseconds of the latency buckets, slower requests land in the last bucket.
+const LATENCY_BUCKETS_US: [u64; 6] = [10, 100, 1_000, 10_000, 100_000, 1_000_000];
+const NUM_LATENCY_BUCKETS: usize = LATENCY_BUCKETS_US.len() + 1;
+
+thread_local! {
+    static REPLIED_ERROR: Cell<bool> = const { Cell::new(false) };
+}
+
+// backend errors by OpenDAL error kind, counted where they are converted.
+static BACKEND_ERRORS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+/// Marks the request handled by the current thread as failed.
+pub fn mark_error() {
+    REPLIED_ERROR.with(|replied| replied.set(true));
+}
+
+/// Clears the failure mark before a request is handled and returns the previous mark.
+pub fn take_error() -> bool {
+    REPLIED_ERROR.with(|replied| replied.replace(false))
+}
+
+pub fn record_backend_error(kind: &'static str) {
+    *BACKEND_ERRORS.lock().unwrap().entry(kind).or_default() += 1;
+}
+
+pub fn backend_errors() -> BTreeMap<&'static str, u64> {
+    BACKEND_ERRORS.lock().unwrap().clone()
+}
+
+#[derive(Default)]
+struct OpcodeMetrics {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    latency: [AtomicU64; NUM_LATENCY_BUCKETS],
+}
+
+#[derive(Debug)]
+pub struct OpcodeSnapshot {
+    pub opcode: u32,
+    pub calls: u64,
+    pub errors: u64,
+    pub latency: [u64; NUM_LATENCY_BUCKETS],
+}
+
+pub struct Metrics {
+    opcodes: [OpcodeMetrics; MAX_OPCODES],
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            opcodes: std::array::from_fn(|_| OpcodeMetrics::default()),
+        }
+    }
+
+    pub fn record(&self, opcode: u32, elapsed: Duration, error: bool) {
+        let Some(metrics) = self.opcodes.get(opcode as usize) else {
+            return;
+        };
+        metrics.calls.fetch_add(1, Ordering::Relaxed);
+        if error {
+            metrics.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let elapsed = elapsed.as_micros() as u64;
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|bound| elapsed <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_US.len());
+        metrics.latency[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Vec<OpcodeSnapshot> {
+        self.opcodes
+            .iter()
+            .enumerate()
+            .filter(|(_, metrics)| metrics.calls.load(Ordering::Relaxed) > 0)
+            .map(|(opcode, metrics)| OpcodeSnapshot {
+                opcode: opcode as u32,
+                calls: metrics.calls.load(Ordering::Relaxed),
+                errors: metrics.errors.load(Ordering::Relaxed),
+                latency: std::array::from_fn(|i| metrics.latency[i].load(Ordering::Relaxed)),
+            })
+            .collect()
+    }
+}
+
+impl OpcodeSnapshot {
+    /// Latency buckets labelled `<=bound_us`, followed by the one for slower requests.
+    fn latency_buckets(&self) -> Vec<(String, u64)> {
+        let mut buckets: Vec<(String, u64)> = LATENCY_BUCKETS_US
+            .iter()
+            .zip(self.latency.iter())
+            .map(|(bound, count)| (format!("<={}us", bound), *count))
+            .collect();
+        buckets.push((
+            format!(">{}us", LATENCY_BUCKETS_US[LATENCY_BUCKETS_US.len() - 1]),
+            self.latency[LATENCY_BUCKETS_US.len()],
+        ));
+        buckets
+    }
+
+    /// Formats the latency histogram as `<=bound_us:count` pairs.
+    pub fn latency_histogram(&self) -> String {
+        self.latency_buckets()
+            .iter()
+            .map(|(bucket, count)| format!("{}:{}", bucket, count))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn to_json(&self) -> String {
+        let latency: Vec<String> = self
+            .latency_buckets()
+            .iter()
+            .map(|(bucket, count)| format!("\"{}\":{}", bucket, count))
+            .collect();
+        format!(
+            "{{\"opcode\":{},\"calls\":{},\"errors\":{},\"latency\":{{{}}}}}",
+            self.opcode,
+            self.calls,
+            self.errors,
+            latency.join(",")
+        )
+    }
+}
+
+/// Counters dumped on the control socket.
+pub struct Stats {
+    pub opcodes: Vec<OpcodeSnapshot>,
+    pub inodes: usize,
+    pub handles: u64,
+    pub writers: usize,
+    pub backend_errors: BTreeMap<&'static str, u64>,
+}
+
+impl Stats {
+    pub fn to_json(&self) -> String {
+        let requests: u64 = self.opcodes.iter().map(|opcode| opcode.calls).sum();
+        let errors: u64 = self.opcodes.iter().map(|opcode| opcode.errors).sum();
+        let opcodes: Vec<String> = self.opcodes.iter().map(OpcodeSnapshot::to_json).collect();
+        // error kinds are plain identifiers, so they need no escaping.
+        let backend_errors: Vec<String> = self
+            .backend_errors
+            .iter()
+            .map(|(kind, count)| format!("\"{}\":{}", kind, count))
+            .collect();
+        format!(
+            "{{\"requests\":{},\"errors\":{},\"inodes\":{},\"handles\":{},\"writers\":{},\"opcodes\":[{}],\"backend_errors\":{{{}}}}}",
+            requests,
+            errors,
+            self.inodes,
+            self.handles,
+            self.writers,
+            opcodes.join(","),
+            backend_errors.join(",")
+        )
+    }
+}
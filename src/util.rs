@@ -163,6 +163,11 @@ impl<'a, B: Bitmap + BitmapSlice + 'static> Reader<'a, B> {
         })
     }
 
+    /// Bytes of the chain not read yet.
+    pub fn available_bytes(&self) -> usize {
+        self.buffer.buffers.iter().map(|vs| vs.len()).sum()
+    }
+
     pub fn read_obj<T: ByteValued>(&mut self) -> io::Result<T> {
         let mut obj = MaybeUninit::<T>::uninit();
         let buf =
@@ -182,6 +187,18 @@ impl<'a, B: Bitmap + BitmapSlice + 'static> Reader<'a, B> {
     }
 }
 
+impl<'a> Reader<'a> {
+    /// Reads a request already copied out of guest memory.
+    pub fn from_bytes(buf: &'a mut [u8]) -> Reader<'a> {
+        Reader {
+            buffer: DescriptorChainConsumer {
+                buffers: VecDeque::from([VolatileSlice::from(buf)]),
+                bytes_consumed: 0,
+            },
+        }
+    }
+}
+
 impl<'a, B: BitmapSlice> io::Read for Reader<'a, B> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.buffer
@@ -268,6 +285,18 @@ impl<'a, B: Bitmap + BitmapSlice + 'static> Writer<'a, B> {
     }
 }
 
+#[cfg(test)]
+impl<'a> Writer<'a> {
+    pub fn from_bytes(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer {
+            buffer: DescriptorChainConsumer {
+                buffers: VecDeque::from([VolatileSlice::from(buf)]),
+                bytes_consumed: 0,
+            },
+        }
+    }
+}
+
 impl<'a, B: BitmapSlice> Write for Writer<'a, B> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.buffer